@@ -27,4 +27,12 @@ o.default = "/etc/xunlei"
 o = s:option(Value, "download_path", translate("Default Download Path"))
 o.default = "/tmp/downloads"
 
+o = s:option(DynamicList, "allow", translate("Allowed Clients"))
+o.description = translate("IP addresses or CIDR blocks allowed to access the web interface, everyone when empty")
+o.datatype = "or(ipaddr,cidr)"
+
+o = s:option(DynamicList, "deny", translate("Denied Clients"))
+o.description = translate("IP addresses or CIDR blocks denied access to the web interface")
+o.datatype = "or(ipaddr,cidr)"
+
 return m
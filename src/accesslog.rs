@@ -0,0 +1,307 @@
+use anyhow::Context;
+use std::{
+    fs::File,
+    io::{Read, Write},
+    net::IpAddr,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{acl, standard};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    // Apache combined log format followed by the duration in microseconds
+    Combined,
+    // One JSON object per line
+    Json,
+}
+
+impl AccessLogFormat {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => anyhow::bail!(format!(
+                "`{}` isn't a access log format (combined, json)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for AccessLogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessLogFormat::Combined => write!(f, "combined"),
+            AccessLogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+struct Output {
+    path: PathBuf,
+    file: File,
+    format: AccessLogFormat,
+}
+
+// Per-request log of the UI, opened in append mode so that copytruncate rotation works
+pub struct AccessLog {
+    output: Mutex<Option<Output>>,
+}
+
+impl AccessLog {
+    pub fn new(path: Option<&Path>, format: AccessLogFormat) -> anyhow::Result<Arc<Self>> {
+        let log = Arc::new(Self {
+            output: Mutex::new(None),
+        });
+        log.configure(path, format)?;
+        Ok(log)
+    }
+
+    pub fn configure(&self, path: Option<&Path>, format: AccessLogFormat) -> anyhow::Result<()> {
+        let output = match path {
+            Some(path) => Some(Output {
+                path: path.to_path_buf(),
+                file: open(path)?,
+                format,
+            }),
+            None => None,
+        };
+        *self.output.lock().unwrap() = output;
+        Ok(())
+    }
+
+    // Reopen the file after it has been moved away by an external logrotate
+    pub fn reopen(&self) -> anyhow::Result<()> {
+        if let Some(output) = self.output.lock().unwrap().as_mut() {
+            output.file = open(&output.path)?;
+        }
+        Ok(())
+    }
+
+    // Log the request once its response body has been sent
+    pub fn record(
+        self: &Arc<Self>,
+        request: &rouille::Request,
+        remote: IpAddr,
+        response: rouille::Response,
+        started: Instant,
+    ) -> rouille::Response {
+        if self.output.lock().unwrap().is_none() {
+            return response;
+        }
+        let entry = Entry {
+            remote: acl::canonical(remote),
+            time: chrono::Local::now(),
+            method: request.method().to_string(),
+            url: request.raw_url().to_string(),
+            status: response.status_code,
+            referer: request.header("Referer").map(str::to_string),
+            user_agent: request.header("User-Agent").map(str::to_string),
+            started,
+        };
+        let mut response = response;
+        let (reader, size) = std::mem::replace(&mut response.data, rouille::ResponseBody::empty())
+            .into_reader_and_size();
+        let body = CountingBody {
+            reader,
+            bytes: 0,
+            entry: Some(entry),
+            log: self.clone(),
+        };
+        response.data = match size {
+            Some(size) => rouille::ResponseBody::from_reader_and_size(body, size),
+            None => rouille::ResponseBody::from_reader(body),
+        };
+        response
+    }
+
+    fn write(&self, entry: &Entry, bytes: u64) {
+        let mut output = self.output.lock().unwrap();
+        let output = match output.as_mut() {
+            Some(output) => output,
+            None => return,
+        };
+        let duration = entry.started.elapsed();
+        let line = match output.format {
+            AccessLogFormat::Combined => format!(
+                "{} - - [{}] \"{} {} HTTP/1.1\" {} {} \"{}\" \"{}\" {}",
+                entry.remote,
+                entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                escape(&entry.method),
+                escape(&entry.url),
+                entry.status,
+                bytes,
+                escape(entry.referer.as_deref().unwrap_or("-")),
+                escape(entry.user_agent.as_deref().unwrap_or("-")),
+                duration.as_micros()
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "time": entry.time.to_rfc3339(),
+                "remote_addr": entry.remote.to_string(),
+                "method": entry.method,
+                "path": entry.url,
+                "status": entry.status,
+                "bytes": bytes,
+                "duration_us": duration.as_micros() as u64,
+                "referer": entry.referer,
+                "user_agent": entry.user_agent,
+            })
+            .to_string(),
+        };
+        if let Err(e) = writeln!(output.file, "{}", line) {
+            log::error!(
+                "[AccessLog] Failed to write {}: {}",
+                output.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<File> {
+    if let Some(parent) = path.parent() {
+        if parent.as_os_str().is_empty().not() && parent.exists().not() {
+            standard::create_dir_all(parent, 0o755)?;
+        }
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Failed to open access log: {}", path.display()))
+}
+
+// Quotes and control characters inside the combined format fields would break parsers
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | '"' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct Entry {
+    remote: IpAddr,
+    time: chrono::DateTime<chrono::Local>,
+    method: String,
+    url: String,
+    status: u16,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    started: Instant,
+}
+
+// Response body that counts the bytes sent and writes the log entry when dropped
+struct CountingBody {
+    reader: Box<dyn Read + Send>,
+    bytes: u64,
+    entry: Option<Entry>,
+    log: Arc<AccessLog>,
+}
+
+impl Read for CountingBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.log.write(&entry, self.bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn escape_fields() {
+        let cases = [
+            ("/webman/index.cgi?a=1", "/webman/index.cgi?a=1"),
+            (r#"/"quoted""#, r#"/\"quoted\""#),
+            (r"/back\slash", r"/back\\slash"),
+            ("/a\r\nb\t\x00\x7f", r"/a\x0d\x0ab\x09\x00\x7f"),
+            ("/\u{85}next", r"/\x85next"),
+            // Bytes that aren't UTF-8 reach the log percent-encoded
+            ("/%ff%fe", "/%ff%fe"),
+            ("/迅雷", "/迅雷"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(escape(value), expected, "{:?}", value);
+        }
+    }
+
+    // Log one request and return the line written for it
+    fn log_line(format: AccessLogFormat, url: &str, headers: Vec<(String, String)>) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::new(Some(&path), format).unwrap();
+        let request = rouille::Request::fake_http_from(
+            "192.0.2.1:4000".parse().unwrap(),
+            "GET",
+            url,
+            headers,
+            vec![],
+        );
+        let started = Instant::now() - Duration::from_millis(5);
+        let response = log.record(
+            &request,
+            "192.0.2.1".parse().unwrap(),
+            rouille::Response::text("hello"),
+            started,
+        );
+        let (mut body, _) = response.data.into_reader_and_size();
+        std::io::copy(&mut body, &mut std::io::sink()).unwrap();
+        drop(body);
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn combined() {
+        let line = log_line(
+            AccessLogFormat::Combined,
+            "/a\"b",
+            vec![(String::from("User-Agent"), String::from("curl \"8\""))],
+        );
+        assert!(line.starts_with("192.0.2.1 - - ["), "{}", line);
+        assert_eq!(line.lines().count(), 1, "{}", line);
+        let (head, duration) = line.trim_end().rsplit_once(' ').unwrap();
+        assert!(
+            head.ends_with(r#"] "GET /a\"b HTTP/1.1" 200 5 "-" "curl \"8\"""#),
+            "{}",
+            line
+        );
+        assert!(duration.parse::<u64>().unwrap() >= 5_000, "{}", line);
+    }
+
+    #[test]
+    fn json() {
+        let line = log_line(AccessLogFormat::Json, "/a\"b?c=%ff", vec![]);
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["remote_addr"], "192.0.2.1");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/a\"b?c=%ff");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes"], 5);
+        assert!(entry["duration_us"].as_u64().unwrap() >= 5_000, "{}", line);
+        assert!(entry["referer"].is_null());
+        assert!(entry.get("duration_ms").is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::net::IpAddr;
+
+// Address block such as 192.168.1.0/24 or fd00::/8, a bare address is a single host
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow::anyhow!(format!("`{}` isn't a ip address or CIDR", s)))?;
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow::anyhow!(format!("`{}` has an invalid prefix length", s)))?,
+            None => max,
+        };
+        // Clients are matched in canonical form, so IPv4-mapped blocks must be IPv4 blocks
+        if let IpAddr::V6(v6) = addr {
+            if let (Some(v4), true) = (v6.to_ipv4_mapped(), prefix >= 96) {
+                return Ok(Self {
+                    addr: IpAddr::V4(v4),
+                    prefix: prefix - 96,
+                });
+            }
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses
+pub fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+// Client address filter of the web UI: denied blocks win, an empty allow list allows everyone
+#[derive(Clone, Default, PartialEq)]
+pub struct Acl {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl Acl {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("192.168.1.0/24", Some("192.168.1.0/24")),
+            (" 10.0.0.1 ", Some("10.0.0.1/32")),
+            ("0.0.0.0/0", Some("0.0.0.0/0")),
+            ("10.0.0.0/32", Some("10.0.0.0/32")),
+            ("10.0.0.0/33", None),
+            ("10.0.0.0/-1", None),
+            ("10.0.0.0/", None),
+            ("10.0.0.0/x", None),
+            ("fd00::/8", Some("fd00::/8")),
+            ("::1", Some("::1/128")),
+            ("fd00::/128", Some("fd00::/128")),
+            ("fd00::/129", None),
+            ("::ffff:10.0.0.0/104", Some("10.0.0.0/8")),
+            ("::ffff:192.0.2.1", Some("192.0.2.1/32")),
+            ("::ffff:0.0.0.0/96", Some("0.0.0.0/0")),
+            ("::ffff:0:0/80", Some("::ffff:0.0.0.0/80")),
+            ("10.0.0.256/8", None),
+            ("localhost", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            let parsed = Cidr::parse(input).ok().map(|cidr| cidr.to_string());
+            assert_eq!(parsed.as_deref(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn contains() {
+        let cases = [
+            ("192.168.1.0/24", "192.168.1.7", true),
+            ("192.168.1.0/24", "192.168.2.7", false),
+            ("192.168.1.0/24", "::ffff:192.168.1.7", true),
+            ("0.0.0.0/0", "203.0.113.9", true),
+            ("0.0.0.0/0", "2001:db8::1", false),
+            ("10.0.0.1", "10.0.0.1", true),
+            ("10.0.0.1", "10.0.0.2", false),
+            ("::ffff:10.0.0.0/104", "10.20.30.40", true),
+            ("::ffff:10.0.0.0/104", "::ffff:10.20.30.40", true),
+            ("::ffff:10.0.0.0/104", "11.0.0.1", false),
+            ("fd00::/8", "fd12::1", true),
+            ("fd00::/8", "fe80::1", false),
+            ("::/0", "2001:db8::1", true),
+            ("::/0", "192.0.2.1", false),
+        ];
+        for (cidr, ip, expected) in cases {
+            let contains = Cidr::parse(cidr).unwrap().contains(ip.parse().unwrap());
+            assert_eq!(contains, expected, "{} contains {}", cidr, ip);
+        }
+    }
+
+    #[test]
+    fn permits() {
+        let cidrs = |list: &[&str]| {
+            list.iter()
+                .map(|cidr| Cidr::parse(cidr).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let open = Acl::default();
+        assert!(open.permits("203.0.113.9".parse().unwrap()));
+
+        let acl = Acl {
+            allow: cidrs(&["192.168.0.0/16", "fd00::/8"]),
+            deny: cidrs(&["192.168.1.13"]),
+        };
+        let cases = [
+            ("192.168.1.7", true),
+            ("::ffff:192.168.1.7", true),
+            ("192.168.1.13", false),
+            ("::ffff:192.168.1.13", false),
+            ("10.0.0.1", false),
+            ("fd00::1", true),
+            ("2001:db8::1", false),
+        ];
+        for (ip, expected) in cases {
+            assert_eq!(acl.permits(ip.parse().unwrap()), expected, "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::RngCore;
+use std::{
+    collections::HashMap,
+    io::Write,
+    ops::Not,
+    os::unix::prelude::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::standard;
+
+pub const SESSION_COOKIE: &str = "xunlei_session";
+// Live sessions of each kind kept at most, the oldest one of the kind is evicted to make room
+// for a new one
+const MAX_SESSIONS: usize = 1024;
+
+const LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Xunlei</title>
+<style>
+body { font-family: sans-serif; background: #f4f5f7; display: flex; justify-content: center; margin-top: 15vh; }
+form { background: #fff; padding: 2em; border-radius: 8px; box-shadow: 0 1px 4px rgba(0, 0, 0, .15); width: 18em; }
+input { width: 100%; box-sizing: border-box; padding: .6em; margin: .5em 0; }
+p { color: #c0392b; }
+</style>
+</head>
+<body>
+<form method="post" action="login">
+<h3>Xunlei</h3>
+{error}
+<input type="password" name="password" placeholder="Password" autofocus required>
+<input type="submit" value="Login">
+</form>
+</body>
+</html>
+"#;
+
+// Password stored as an Argon2 PHC string in the config directory
+fn password_file(config_path: &Path) -> PathBuf {
+    config_path.join(standard::PASSWORD_FILE)
+}
+
+// Hash the password and store it, or remove the stored password when none is given
+pub fn set_password(config_path: &Path, password: Option<&str>) -> anyhow::Result<()> {
+    let path = password_file(config_path);
+    let password = match password {
+        Some(password) => password,
+        None => {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+    };
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow::anyhow!(e))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+
+    if config_path.exists().not() {
+        standard::create_dir_all(config_path, 0o755)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", hash))
+        .context(format!("Failed to write password file: {}", path.display()))
+}
+
+// Read a secret such as the metrics token from a file that only its owner may access
+pub fn read_secret(path: &Path) -> anyhow::Result<String> {
+    let metadata = std::fs::metadata(path).context(format!("Failed to read {}", path.display()))?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        anyhow::bail!(format!(
+            "{} is accessible to other users, run `chmod 600 {}`",
+            path.display(),
+            path.display()
+        ));
+    }
+    let secret = std::fs::read_to_string(path)?.trim().to_string();
+    if secret.is_empty() {
+        anyhow::bail!(format!("{} is empty", path.display()));
+    }
+    Ok(secret)
+}
+
+struct Session {
+    expiry: Instant,
+    // DSM CSRF token the UI sends back on state-changing requests
+    syno_token: String,
+    // Opened without a password while authentication was disabled
+    anonymous: bool,
+}
+
+// Login sessions of the web UI, kept in memory so that a restart logs everybody out
+pub struct Auth {
+    config: Mutex<(PathBuf, Duration)>,
+    // Sessions by cookie token
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Auth {
+    pub fn new(config_path: &Path, ttl: Duration) -> Arc<Self> {
+        let auth = Arc::new(Self {
+            config: Mutex::new((password_file(config_path), ttl)),
+            sessions: Mutex::new(HashMap::new()),
+        });
+        if auth.enabled().not() {
+            log::warn!(
+                "[Auth] The UI is not password protected, run `xunlei passwd` to set a password"
+            );
+        }
+        auth
+    }
+
+    pub fn configure(&self, config_path: &Path, ttl: Duration) {
+        *self.config.lock().unwrap() = (password_file(config_path), ttl);
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.config.lock().unwrap().1
+    }
+
+    // Authentication is enforced once a password has been set
+    pub fn enabled(&self) -> bool {
+        self.config.lock().unwrap().0.exists()
+    }
+
+    // Sessions opened before a password was set don't count
+    pub fn authenticated(&self, request: &rouille::Request) -> bool {
+        if self.enabled().not() {
+            return true;
+        }
+        let sessions = self.sessions.lock().unwrap();
+        session(request)
+            .and_then(|token| sessions.get(token))
+            .is_some_and(|session| session.expiry > Instant::now() && session.anonymous.not())
+    }
+
+    // SynoToken of the valid session the request belongs to
+    pub fn syno_token(&self, request: &rouille::Request) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(session(request)?)?;
+        match session.expiry > Instant::now() {
+            true => Some(session.syno_token.clone()),
+            false => None,
+        }
+    }
+
+    // Whether the request carries the SynoToken of its session, as DSM requires for writes
+    pub fn check_syno_token(&self, request: &rouille::Request) -> bool {
+        let expected = match self.syno_token(request) {
+            Some(token) => token,
+            None => return false,
+        };
+        match request.header("X-SYNO-TOKEN") {
+            Some(token) => token_eq(token, &expected),
+            None => request
+                .get_param("SynoToken")
+                .is_some_and(|token| token_eq(&token, &expected)),
+        }
+    }
+
+    // Verify the password and open a session, returning its token
+    pub fn login(&self, password: &str) -> anyhow::Result<Option<String>> {
+        let (path, ttl) = self.config.lock().unwrap().clone();
+        let stored = std::fs::read_to_string(&path)
+            .context(format!("Failed to read password file: {}", path.display()))?;
+        let hash = PasswordHash::new(stored.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid password file {}: {}", path.display(), e))?;
+        if Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(self.open(ttl, false).0))
+    }
+
+    // Start a session without a password, used when authentication is disabled. Anybody can open
+    // these, so they only ever evict each other and never a password session.
+    pub fn open_anonymous(&self) -> (String, String) {
+        self.open(self.ttl(), true)
+    }
+
+    // Start a session, returning its cookie token and SynoToken
+    fn open(&self, ttl: Duration, anonymous: bool) -> (String, String) {
+        let token = random_token(32);
+        let syno_token = random_token(16);
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expiry > now);
+        let same_kind = || {
+            sessions
+                .iter()
+                .filter(|(_, session)| session.anonymous == anonymous)
+        };
+        if same_kind().count() >= MAX_SESSIONS {
+            let oldest = same_kind()
+                .min_by_key(|(_, session)| session.expiry)
+                .map(|(token, _)| token.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+        sessions.insert(
+            token.clone(),
+            Session {
+                expiry: now + ttl,
+                syno_token: syno_token.clone(),
+                anonymous,
+            },
+        );
+        (token, syno_token)
+    }
+
+    pub fn logout(&self, request: &rouille::Request) {
+        if let Some(token) = session(request) {
+            self.sessions.lock().unwrap().remove(token);
+        }
+    }
+}
+
+// Compare secrets without leaking the length of the common prefix through timing
+pub fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn random_token(len: usize) -> String {
+    let mut token = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut token);
+    token.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn session(request: &rouille::Request) -> Option<&str> {
+    rouille::input::cookies(request)
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+// Set-Cookie value of a session, an empty token expires the cookie
+pub fn session_cookie(token: &str, max_age: Duration, secure: bool) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        SESSION_COOKIE,
+        token,
+        max_age.as_secs(),
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+pub fn login_page(error: Option<&str>) -> rouille::Response {
+    let error = error
+        .map(|error| format!("<p>{}</p>", error))
+        .unwrap_or_default();
+    rouille::Response::html(LOGIN_PAGE.replace("{error}", &error))
+}
+
+// Ask for the new password twice on a terminal, or read a single line from stdin otherwise
+pub fn prompt_password() -> anyhow::Result<String> {
+    let tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    let password = read_password("New password: ", tty)?;
+    if password.is_empty() {
+        anyhow::bail!("The password must not be empty");
+    }
+    if tty && read_password("Retype new password: ", tty)? != password {
+        anyhow::bail!("The passwords do not match");
+    }
+    Ok(password)
+}
+
+fn read_password(prompt: &str, tty: bool) -> anyhow::Result<String> {
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if tty {
+        eprint!("{}", prompt);
+        unsafe {
+            libc::tcgetattr(libc::STDIN_FILENO, &mut termios);
+            let mut silent = termios;
+            silent.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent);
+        }
+    }
+    let mut line = String::new();
+    let result = std::io::stdin().read_line(&mut line);
+    if tty {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        eprintln!();
+    }
+    result.context("Failed to read password")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(token: &str) -> rouille::Request {
+        rouille::Request::fake_http(
+            "GET",
+            "/",
+            vec![(
+                String::from("Cookie"),
+                format!("{}={}", SESSION_COOKIE, token),
+            )],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn anonymous_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = Auth::new(dir.path(), Duration::from_secs(3600));
+        let (anonymous, syno_token) = auth.open_anonymous();
+        assert!(auth.authenticated(&request(&anonymous)));
+        assert_eq!(auth.syno_token(&request(&anonymous)), Some(syno_token));
+
+        // A password set later doesn't let the anonymous session in
+        let (password, _) = auth.open(auth.ttl(), false);
+        std::fs::write(dir.path().join(standard::PASSWORD_FILE), "hash").unwrap();
+        assert!(auth.authenticated(&request(&anonymous)).not());
+        assert!(auth.authenticated(&request(&password)));
+        assert!(auth.authenticated(&request("unknown")).not());
+
+        // Flooding anonymous sessions evicts only anonymous ones
+        for _ in 0..MAX_SESSIONS + 10 {
+            auth.open_anonymous();
+        }
+        let sessions = auth.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), MAX_SESSIONS + 1);
+        assert!(sessions.contains_key(&password));
+        assert!(sessions.contains_key(&anonymous).not());
+    }
+}
@@ -0,0 +1,263 @@
+use std::{io::Read, ops::Not};
+
+// Absolute URLs of the Xunlei UI that are moved under the base path in HTML, JS and CSS responses
+const UI_ROOT: &str = "/webman/";
+
+// Characters that open a URL in markup, scripts and stylesheets
+const URL_DELIMITERS: [char; 5] = ['"', '\'', '`', '(', '='];
+
+// Content types whose bodies get their absolute URLs rewritten
+const REWRITE_CONTENT_TYPES: [&str; 5] = [
+    "text/html",
+    "text/css",
+    "text/javascript",
+    "application/javascript",
+    "application/x-javascript",
+];
+
+// Path prefix of the UI behind a reverse proxy such as `/xunlei`, empty when served at the root
+#[derive(Clone, Default, PartialEq)]
+pub struct BasePath(String);
+
+impl BasePath {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let path = s.trim().trim_end_matches('/');
+        if path.is_empty() {
+            return Ok(Self::default());
+        }
+        if path.starts_with('/').not()
+            || path.starts_with("//")
+            || path.contains(|c: char| c.is_ascii_whitespace() || matches!(c, '?' | '#' | '"'))
+        {
+            anyhow::bail!(format!("`{}` isn't a absolute path such as /xunlei", s))
+        }
+        Ok(Self(path.to_string()))
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The request relative to the base path, None when it is outside of it. Matched against the
+    // raw URL, rouille's remove_prefix panics on a prefix that is only there once decoded.
+    pub fn strip(&self, request: &rouille::Request) -> Option<rouille::Request> {
+        let rest = request.raw_url().strip_prefix(&self.0)?;
+        if rest.is_empty().not() && rest.starts_with(['/', '?']).not() {
+            return None;
+        }
+        request.remove_prefix(&self.0)
+    }
+
+    // Prefix a local absolute path, leaving full and protocol-relative URLs alone
+    pub fn apply(&self, location: &str) -> String {
+        match location.starts_with('/') && location.starts_with("//").not() {
+            true => format!("{}{}", self.0, location),
+            false => location.to_string(),
+        }
+    }
+
+    // Move the Location header and the absolute UI URLs of the body under the base path
+    pub fn rewrite(&self, mut response: rouille::Response) -> rouille::Response {
+        if self.is_root() {
+            return response;
+        }
+        for (name, value) in response.headers.iter_mut() {
+            if name.eq_ignore_ascii_case("Location") {
+                *value = self.apply(value).into();
+            }
+        }
+
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_ascii_lowercase())
+        };
+        let rewritable = header("Content-Type")
+            .map(|content_type| {
+                let mime = content_type.split(';').next().unwrap_or_default().trim();
+                REWRITE_CONTENT_TYPES.contains(&mime)
+            })
+            .unwrap_or(false);
+        // Compressed bodies are passed through untouched
+        let encoded = header("Content-Encoding")
+            .map(|encoding| encoding != "identity")
+            .unwrap_or(false);
+        if rewritable.not() || encoded {
+            return response;
+        }
+
+        let (mut reader, _) = std::mem::replace(&mut response.data, rouille::ResponseBody::empty())
+            .into_reader_and_size();
+        let mut body = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut body) {
+            log::error!("[XunleiLauncher] Failed to read UI response body: {}", e);
+            return rouille::Response::text("Bad Gateway").with_status_code(502);
+        }
+        let mut body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(e) => {
+                response.data = rouille::ResponseBody::from_data(e.into_bytes());
+                return response;
+            }
+        };
+        let root = format!("{}{}", self.0, UI_ROOT);
+        for delimiter in URL_DELIMITERS {
+            body = body.replace(
+                &format!("{}{}", delimiter, UI_ROOT),
+                &format!("{}{}", delimiter, root),
+            );
+        }
+        response
+            .headers
+            .retain(|(name, _)| name.eq_ignore_ascii_case("Content-Length").not());
+        response.data = rouille::ResponseBody::from_string(body);
+        response
+    }
+}
+
+impl std::fmt::Display for BasePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.is_root() {
+            true => write!(f, "/"),
+            false => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: rouille::Response) -> String {
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        body
+    }
+
+    fn header(response: &rouille::Response, name: &str) -> Option<String> {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("/xunlei", Some("/xunlei")),
+            (" /xunlei/ ", Some("/xunlei")),
+            ("/a/b", Some("/a/b")),
+            ("/", Some("/")),
+            ("", Some("/")),
+            ("xunlei", None),
+            ("//xunlei", None),
+            ("/xun lei", None),
+            ("/xunlei?a=1", None),
+            ("/xunlei#top", None),
+        ];
+        for (input, expected) in cases {
+            let parsed = BasePath::parse(input).ok().map(|path| path.to_string());
+            assert_eq!(parsed.as_deref(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn strip() {
+        let base = BasePath::parse("/xunlei").unwrap();
+        let cases = [
+            (
+                "/xunlei/webman/login.cgi?a=1",
+                Some("/webman/login.cgi?a=1"),
+            ),
+            ("/xunlei/", Some("/")),
+            ("/xunlei", Some("")),
+            ("/xunlei?a=1", Some("?a=1")),
+            ("/xunlei/%2e%2e/etc", Some("/%2e%2e/etc")),
+            ("/xunleix", None),
+            ("/xunleix/webman/", None),
+            ("/webman/", None),
+            ("/", None),
+            // Only the decoded URL starts with the base path
+            ("/%78unlei/webman/", None),
+            ("/xunlei%2fwebman/", None),
+        ];
+        for (url, expected) in cases {
+            let request = rouille::Request::fake_http("GET", url, vec![], vec![]);
+            let stripped = base
+                .strip(&request)
+                .map(|request| request.raw_url().to_string());
+            assert_eq!(stripped.as_deref(), expected, "{:?}", url);
+        }
+
+        let root = BasePath::default();
+        let request = rouille::Request::fake_http("GET", "/webman/?a=1", vec![], vec![]);
+        let stripped = root.strip(&request).map(|r| r.raw_url().to_string());
+        assert_eq!(stripped.as_deref(), Some("/webman/?a=1"));
+    }
+
+    #[test]
+    fn apply() {
+        let base = BasePath::parse("/xunlei").unwrap();
+        let cases = [
+            ("/login", "/xunlei/login"),
+            ("/webman/?a=1", "/xunlei/webman/?a=1"),
+            ("//example.com/login", "//example.com/login"),
+            ("https://example.com/login", "https://example.com/login"),
+            ("login", "login"),
+            ("", ""),
+        ];
+        for (location, expected) in cases {
+            assert_eq!(base.apply(location), expected, "{:?}", location);
+        }
+        assert_eq!(BasePath::default().apply("/login"), "/login");
+    }
+
+    #[test]
+    fn rewrite() {
+        let base = BasePath::parse("/xunlei").unwrap();
+        let html = concat!(
+            r#"<a href="/webman/a"><img src='/webman/b'>"#,
+            r#"<style>i{background:url(/webman/c)}</style>"#,
+            r#"<script>fetch(`/webman/d`);x=/webman/e</script>"#,
+            r#"<a href="https://example.com/webman/f">/webman/g /webmanx "/other/"</a>"#,
+        );
+        let response = base.rewrite(rouille::Response::html(html));
+        assert_eq!(header(&response, "Content-Length"), None);
+        assert_eq!(
+            body(response),
+            concat!(
+                r#"<a href="/xunlei/webman/a"><img src='/xunlei/webman/b'>"#,
+                r#"<style>i{background:url(/xunlei/webman/c)}</style>"#,
+                r#"<script>fetch(`/xunlei/webman/d`);x=/xunlei/webman/e</script>"#,
+                r#"<a href="https://example.com/webman/f">/webman/g /webmanx "/other/"</a>"#,
+            )
+        );
+
+        let css = rouille::Response::from_data("text/css; charset=utf-8", "a{b:url('/webman/x')}");
+        assert_eq!(body(base.rewrite(css)), "a{b:url('/xunlei/webman/x')}");
+
+        // Other content types and compressed bodies are left alone
+        let json = rouille::Response::json(&"/webman/");
+        assert_eq!(body(base.rewrite(json)), r#""/webman/""#);
+        let gzip =
+            rouille::Response::html(r#""/webman/""#).with_unique_header("Content-Encoding", "gzip");
+        assert_eq!(body(base.rewrite(gzip)), r#""/webman/""#);
+        let root = BasePath::default().rewrite(rouille::Response::html(r#""/webman/""#));
+        assert_eq!(body(root), r#""/webman/""#);
+
+        let redirect = base.rewrite(rouille::Response::redirect_303("/login"));
+        assert_eq!(
+            header(&redirect, "Location").as_deref(),
+            Some("/xunlei/login")
+        );
+        let redirect = base.rewrite(rouille::Response::redirect_303("https://example.com/"));
+        assert_eq!(
+            header(&redirect, "Location").as_deref(),
+            Some("https://example.com/")
+        );
+    }
+}
@@ -0,0 +1,947 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Read},
+    net::{IpAddr, SocketAddr},
+    ops::Not,
+    os::unix::process::CommandExt,
+    process::Command,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::metrics::{Exposition, Histogram};
+
+// Interval of the watchdog that kills timed out CGI processes
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+// Poll interval while waiting for CGI processes to exit
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct CgiLimits {
+    // Maximum number of CGI processes running at the same time
+    pub max_concurrency: usize,
+    // Maximum number of requests waiting for a free slot
+    pub max_queue: usize,
+    // Kill CGI processes running longer than this, zero disables
+    pub timeout: Duration,
+}
+
+// Upper bound of the CGI response header section
+const MAX_HEADER_SIZE: u64 = 64 * 1024;
+// Upper bound of request bodies without Content-Length, they are spooled to disk
+const MAX_SPOOL_SIZE: u64 = 64 * 1024 * 1024;
+// Upper bound of the output held in memory while the request body is still being written
+const MAX_BUFFERED_SIZE: usize = 1024 * 1024;
+
+pub enum CgiOutput {
+    Response(rouille::Response),
+    // RFC 3875 local redirect, the server must serve the location itself
+    LocalRedirect(String),
+}
+
+// Header section of a CGI response, RFC 3875 section 6
+#[derive(Default)]
+struct CgiHeaders {
+    status: Option<u16>,
+    location: Option<String>,
+    headers: Vec<(
+        std::borrow::Cow<'static, str>,
+        std::borrow::Cow<'static, str>,
+    )>,
+}
+
+impl CgiHeaders {
+    fn parse(reader: &mut impl BufRead) -> Result<CgiHeaders, String> {
+        let mut headers = CgiHeaders::default();
+        let mut reader = reader.take(MAX_HEADER_SIZE);
+        let mut empty = true;
+        loop {
+            let mut line = Vec::new();
+            let n = reader
+                .read_until(b'\n', &mut line)
+                .map_err(|e| format!("read error: {}", e))?;
+            if n == 0 || line.last() != Some(&b'\n') {
+                return Err(match reader.limit() {
+                    0 => format!("header section exceeds {} bytes", MAX_HEADER_SIZE),
+                    _ => String::from("output ended before the end of the header section"),
+                });
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            empty = false;
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("malformed header line `{}`", line))?;
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return Err(format!("invalid header name `{}`", name));
+            }
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("Status") {
+                headers.status = Some(parse_status(value)?);
+            } else if name.eq_ignore_ascii_case("Location") {
+                headers.location = Some(value.to_owned());
+            } else {
+                headers
+                    .headers
+                    .push((name.to_owned().into(), value.to_owned().into()));
+            }
+        }
+        if empty {
+            return Err(String::from("missing header section"));
+        }
+        Ok(headers)
+    }
+}
+
+// `Status: 404 Not Found`, the reason phrase is optional
+fn parse_status(value: &str) -> Result<u16, String> {
+    let code = value.split_ascii_whitespace().next().unwrap_or_default();
+    if code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()) {
+        let code = code.parse::<u16>().map_err(|e| e.to_string())?;
+        if (100..=599).contains(&code) {
+            return Ok(code);
+        }
+    }
+    Err(format!("invalid status `{}`", value))
+}
+
+// A location starting with a single slash is a local redirect, anything else goes to the client
+fn is_local(location: &str) -> bool {
+    location.starts_with('/') && location.starts_with("//").not()
+}
+
+// Request headers that only concern a single connection and are not passed to the CGI
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+    // httpoxy, HTTP_PROXY would be picked up as the proxy of the CGI
+    "Proxy",
+];
+
+// Builds the CGI meta-variables of a request, RFC 3875 section 4.1
+pub struct CgiEnvBuilder<'a> {
+    request: &'a rouille::Request,
+    listen: SocketAddr,
+    remote: SocketAddr,
+    script_name: &'a str,
+    script_filename: &'a str,
+    https: bool,
+}
+
+impl<'a> CgiEnvBuilder<'a> {
+    pub fn new(request: &'a rouille::Request, listen: SocketAddr) -> Self {
+        Self {
+            request,
+            listen,
+            remote: SocketAddr::new(
+                crate::acl::canonical(request.remote_addr().ip()),
+                request.remote_addr().port(),
+            ),
+            script_name: "",
+            script_filename: "",
+            https: request.is_secure(),
+        }
+    }
+
+    // Client address when it differs from the peer of the connection
+    pub fn remote_addr(mut self, remote: SocketAddr) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    // URL path of the script, the rest of the request path becomes PATH_INFO
+    pub fn script_name(mut self, script_name: &'a str) -> Self {
+        self.script_name = script_name;
+        self
+    }
+
+    pub fn script_filename(mut self, script_filename: &'a str) -> Self {
+        self.script_filename = script_filename;
+        self
+    }
+
+    // rouille does not flag requests accepted by its TLS listener as secure
+    pub fn https(mut self, https: bool) -> Self {
+        self.https = https;
+        self
+    }
+
+    pub fn build(&self) -> Vec<(String, String)> {
+        let request = self.request;
+        let mut vars = vec![
+            (
+                "SERVER_SOFTWARE",
+                concat!("xunlei/", env!("CARGO_PKG_VERSION")).to_string(),
+            ),
+            ("SERVER_PROTOCOL", String::from("HTTP/1.1")),
+            ("GATEWAY_INTERFACE", String::from("CGI/1.1")),
+            ("SERVER_NAME", self.server_name()),
+            ("SERVER_ADDR", self.server_addr().to_string()),
+            ("SERVER_PORT", self.listen.port().to_string()),
+            ("REMOTE_ADDR", self.remote.ip().to_string()),
+            ("REMOTE_HOST", self.remote.ip().to_string()),
+            ("REMOTE_PORT", self.remote.port().to_string()),
+            ("REQUEST_METHOD", request.method().to_owned()),
+            ("REQUEST_URI", request.raw_url().to_owned()),
+            ("QUERY_STRING", request.raw_query_string().to_owned()),
+            ("SCRIPT_NAME", self.script_name.to_owned()),
+            ("PATH_INFO", self.path_info()),
+        ];
+        if self.script_filename.is_empty().not() {
+            vars.push(("SCRIPT_FILENAME", self.script_filename.to_owned()));
+        }
+        if self.https {
+            vars.push(("HTTPS", String::from("on")));
+        }
+        if let Some(content_type) = request
+            .header("Content-Type")
+            .filter(|v| v.is_empty().not())
+        {
+            vars.push(("CONTENT_TYPE", content_type.to_owned()));
+        }
+        if let Some(length) = request
+            .header("Content-Length")
+            .filter(|v| v.is_empty().not())
+        {
+            vars.push(("CONTENT_LENGTH", length.to_owned()));
+        }
+
+        let mut vars = vars
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect::<Vec<_>>();
+
+        // Headers named by Connection are hop-by-hop as well
+        let connection = request
+            .header("Connection")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim())
+            .filter(|name| name.is_empty().not())
+            .collect::<Vec<_>>();
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for (name, value) in request.headers() {
+            if name.eq_ignore_ascii_case("Content-Type")
+                || name.eq_ignore_ascii_case("Content-Length")
+                || HOP_BY_HOP_HEADERS
+                    .iter()
+                    .chain(connection.iter())
+                    .any(|h| h.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            // Repeated headers are folded into one comma separated value
+            match headers.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, folded)) => {
+                    folded.push_str(", ");
+                    folded.push_str(value);
+                }
+                None => headers.push((name, value.to_owned())),
+            }
+        }
+        vars.extend(headers);
+        vars
+    }
+
+    fn path_info(&self) -> String {
+        let path = self.request.url();
+        match path.strip_prefix(self.script_name) {
+            Some(rest) if self.script_name.is_empty().not() => rest.to_owned(),
+            _ => path,
+        }
+    }
+
+    // Host header without the port, the listening address if there is none
+    fn server_name(&self) -> String {
+        let name = host_name(self.request.header("Host").unwrap_or_default());
+        match name.is_empty() {
+            true => self.server_addr().to_string(),
+            false => name.to_owned(),
+        }
+    }
+
+    // Local address the client connected to
+    fn server_addr(&self) -> IpAddr {
+        if self.listen.ip().is_unspecified().not() {
+            return self.listen.ip();
+        }
+        // Wildcard listener: the source address routed towards the peer is the one it reached
+        let peer = *self.request.remote_addr();
+        let bind: SocketAddr = match peer {
+            SocketAddr::V4(_) => (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        std::net::UdpSocket::bind(bind)
+            .and_then(|socket| {
+                socket.connect(peer)?;
+                socket.local_addr()
+            })
+            .map(|addr| addr.ip())
+            .unwrap_or_else(|_| self.listen.ip())
+    }
+}
+
+// Host name of a Host header value, without the port and IPv6 brackets
+pub fn host_name(host: &str) -> &str {
+    let host = host.trim();
+    match host.strip_prefix('[') {
+        // [::1]:5055
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map(|(name, _)| name).unwrap_or(host),
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    running: usize,
+    queued: usize,
+}
+
+#[derive(Default)]
+struct CgiMetrics {
+    spawned: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+    failed: AtomicU64,
+    spawn_micros_total: AtomicU64,
+    spawn_micros_max: AtomicU64,
+    // Responses by status code
+    statuses: Mutex<BTreeMap<u16, u64>>,
+    // Lifetime of CGI processes
+    latency: Histogram,
+}
+
+// Runs CGI processes with bounded concurrency and reaps them once they exit
+pub struct CgiExecutor {
+    limits: Mutex<CgiLimits>,
+    slots: Mutex<Slots>,
+    available: Condvar,
+    // Running CGI processes and the deadline after which they are killed
+    processes: Mutex<HashMap<i32, Option<Instant>>>,
+    metrics: CgiMetrics,
+}
+
+// A running slot, released when dropped
+struct Permit(Arc<CgiExecutor>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.slots.lock().unwrap().running -= 1;
+        self.0.available.notify_one();
+    }
+}
+
+impl CgiExecutor {
+    pub fn new(limits: CgiLimits) -> Arc<Self> {
+        let executor = Arc::new(Self {
+            limits: Mutex::new(limits),
+            slots: Mutex::new(Slots::default()),
+            available: Condvar::new(),
+            processes: Mutex::new(HashMap::new()),
+            metrics: CgiMetrics::default(),
+        });
+        let watchdog = Arc::downgrade(&executor);
+        std::thread::Builder::new()
+            .name("cgi-watchdog".to_string())
+            .spawn(move || CgiExecutor::watchdog(watchdog))
+            .expect("[CgiExecutor] Failed to start watchdog thread");
+        executor
+    }
+
+    pub fn set_limits(&self, limits: CgiLimits) {
+        *self.limits.lock().unwrap() = limits;
+        self.available.notify_all();
+    }
+
+    fn limits(&self) -> CgiLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    fn watchdog(executor: Weak<CgiExecutor>) {
+        while let Some(executor) = executor.upgrade() {
+            let now = Instant::now();
+            for (pid, deadline) in executor.processes.lock().unwrap().iter_mut() {
+                if deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+                    log::warn!("[CgiExecutor] CGI process {} timed out, killing it", pid);
+                    unsafe { libc::killpg(*pid, libc::SIGKILL) };
+                    executor.metrics.timed_out.fetch_add(1, Ordering::Relaxed);
+                    *deadline = None;
+                }
+            }
+            drop(executor);
+            std::thread::sleep(WATCHDOG_INTERVAL);
+        }
+    }
+
+    // Wait for a free slot, `None` if the queue is full or the wait timed out
+    fn acquire(self: &Arc<Self>) -> Option<Permit> {
+        let limits = self.limits();
+        let mut slots = self.slots.lock().unwrap();
+        if slots.running >= limits.max_concurrency {
+            if slots.queued >= limits.max_queue {
+                return None;
+            }
+            slots.queued += 1;
+            let deadline = Instant::now() + limits.timeout;
+            while slots.running >= self.limits().max_concurrency {
+                if limits.timeout.is_zero() {
+                    slots = self.available.wait(slots).unwrap();
+                    continue;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    slots.queued -= 1;
+                    return None;
+                }
+                slots = self
+                    .available
+                    .wait_timeout(slots, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+            slots.queued -= 1;
+        }
+        slots.running += 1;
+        Some(Permit(self.clone()))
+    }
+
+    pub fn execute(self: &Arc<Self>, request: &rouille::Request, cmd: Command) -> CgiOutput {
+        let output = self.run(request, cmd);
+        if let CgiOutput::Response(response) = &output {
+            *self
+                .metrics
+                .statuses
+                .lock()
+                .unwrap()
+                .entry(response.status_code)
+                .or_default() += 1;
+        }
+        output
+    }
+
+    fn run(self: &Arc<Self>, request: &rouille::Request, mut cmd: Command) -> CgiOutput {
+        let mut body: Option<Box<dyn Read + Send + '_>> = request
+            .data()
+            .map(|body| Box::new(body) as Box<dyn Read + Send>);
+
+        let permit = match self.acquire() {
+            Some(permit) => permit,
+            None => {
+                self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "[CgiExecutor] Too many CGI requests, rejecting {} {}",
+                    request.method(),
+                    request.raw_url()
+                );
+                return CgiOutput::Response(
+                    rouille::Response::text("Service Unavailable")
+                        .with_status_code(503)
+                        .with_additional_header("Retry-After", "1"),
+                );
+            }
+        };
+
+        // The CGI needs CONTENT_LENGTH, so bodies sent without one (chunked) are spooled first.
+        // Only once a slot is taken, so that the queue bounds the disk space as well.
+        if request.header("Content-Length").is_none()
+            && request.header("Transfer-Encoding").is_some()
+        {
+            if let Some(data) = body.take() {
+                match spool(data) {
+                    Ok(Some((file, length))) => {
+                        cmd.env("CONTENT_LENGTH", length.to_string());
+                        body = Some(Box::new(file));
+                    }
+                    Ok(None) => {
+                        log::warn!(
+                            "[CgiExecutor] Request body exceeds {} bytes, rejecting {} {}",
+                            MAX_SPOOL_SIZE,
+                            request.method(),
+                            request.raw_url()
+                        );
+                        return CgiOutput::Response(
+                            rouille::Response::text("Payload Too Large").with_status_code(413),
+                        );
+                    }
+                    Err(e) => {
+                        log::error!("[CgiExecutor] Failed to spool request body: {}", e);
+                        return CgiOutput::Response(
+                            rouille::Response::text("Internal Server Error").with_status_code(500),
+                        );
+                    }
+                }
+            }
+        }
+
+        let started = Instant::now();
+        // A dedicated process group lets timeouts and shutdown reach the whole CGI tree
+        let mut child = match cmd.process_group(0).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                log::error!("[CgiExecutor] Failed to spawn CGI process: {}", e);
+                return CgiOutput::Response(
+                    rouille::Response::text("Bad Gateway").with_status_code(502),
+                );
+            }
+        };
+        self.record_spawn(started.elapsed());
+
+        // Reap the CGI process once it exits, the slot is released with it
+        let pid = child.id() as i32;
+        let timeout = self.limits().timeout;
+        let deadline = timeout.is_zero().not().then(|| Instant::now() + timeout);
+        self.processes.lock().unwrap().insert(pid, deadline);
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().unwrap();
+        let executor = self.clone();
+        std::thread::spawn(move || {
+            let _ = child.wait();
+            executor.metrics.latency.observe(started.elapsed());
+            executor.processes.lock().unwrap().remove(&pid);
+            drop(permit);
+        });
+
+        let mut stdout = std::io::BufReader::new(stdout);
+        // Write the body while reading the output, otherwise a CGI that answers before
+        // consuming its input blocks on a full pipe while we block on its stdin
+        let output = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || {
+                if let (Some(mut body), Some(mut stdin)) = (body, stdin) {
+                    if let Err(e) = std::io::copy(&mut body, &mut stdin) {
+                        // The CGI may exit without reading the whole body
+                        log::debug!("[CgiExecutor] Failed to write request body: {}", e);
+                    }
+                }
+            });
+
+            let headers = match CgiHeaders::parse(&mut stdout) {
+                Ok(headers) => headers,
+                Err(e) => {
+                    // Nobody reads the output anymore, make sure the writer is not left blocked
+                    unsafe { libc::killpg(pid, libc::SIGKILL) };
+                    return Err(e);
+                }
+            };
+
+            // The response is streamed once the handler returns, keep draining until then
+            let mut buffered = Vec::new();
+            let mut chunk = [0u8; 8192];
+            while writer.is_finished().not() {
+                if buffered.len() >= MAX_BUFFERED_SIZE {
+                    // Killing the CGI is the only way to unblock a writer stuck on its stdin
+                    unsafe { libc::killpg(pid, libc::SIGKILL) };
+                    return Err(format!(
+                        "output exceeds {} bytes before the request body was read",
+                        MAX_BUFFERED_SIZE
+                    ));
+                }
+                match stdout.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => buffered.extend_from_slice(&chunk[..n]),
+                }
+            }
+            Ok((headers, buffered))
+        });
+
+        let (headers, buffered) = match output {
+            Ok(output) => output,
+            Err(e) => {
+                self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "[CgiExecutor] Invalid CGI response for {} {}: {}",
+                    request.method(),
+                    request.raw_url(),
+                    e
+                );
+                return CgiOutput::Response(
+                    rouille::Response::text("Bad Gateway").with_status_code(502),
+                );
+            }
+        };
+
+        match headers.location {
+            Some(location) if headers.status.is_none() && is_local(&location) => {
+                CgiOutput::LocalRedirect(location)
+            }
+            location => {
+                let mut response_headers = headers.headers;
+                let status_code = match location {
+                    Some(location) => {
+                        response_headers.push(("Location".into(), location.into()));
+                        // RFC 3875 6.2.3, a client redirect without status is a 302
+                        headers.status.unwrap_or(302)
+                    }
+                    None => headers.status.unwrap_or(200),
+                };
+                CgiOutput::Response(rouille::Response {
+                    status_code,
+                    headers: response_headers,
+                    data: rouille::ResponseBody::from_reader(
+                        std::io::Cursor::new(buffered).chain(stdout),
+                    ),
+                    upgrade: None,
+                })
+            }
+        }
+    }
+
+    fn record_spawn(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.metrics.spawned.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .spawn_micros_total
+            .fetch_add(micros, Ordering::Relaxed);
+        self.metrics
+            .spawn_micros_max
+            .fetch_max(micros, Ordering::Relaxed);
+        log::debug!("[CgiExecutor] CGI process spawned in {}us", micros);
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let slots = self.slots.lock().unwrap();
+        let spawned = self.metrics.spawned.load(Ordering::Relaxed);
+        let total = self.metrics.spawn_micros_total.load(Ordering::Relaxed);
+        serde_json::json!({
+            "running": slots.running,
+            "queued": slots.queued,
+            "spawned": spawned,
+            "rejected": self.metrics.rejected.load(Ordering::Relaxed),
+            "timed_out": self.metrics.timed_out.load(Ordering::Relaxed),
+            "failed": self.metrics.failed.load(Ordering::Relaxed),
+            "spawn_latency_avg_us": total.checked_div(spawned).unwrap_or(0),
+            "spawn_latency_max_us": self.metrics.spawn_micros_max.load(Ordering::Relaxed),
+        })
+    }
+
+    pub fn render_metrics(&self, out: &mut Exposition) {
+        out.header(
+            "xunlei_cgi_requests_total",
+            "counter",
+            "UI CGI requests by response status.",
+        );
+        for (status, count) in self.metrics.statuses.lock().unwrap().iter() {
+            out.sample(
+                "xunlei_cgi_requests_total",
+                &[("status", &status.to_string())],
+                count,
+            );
+        }
+        self.metrics.latency.render(
+            out,
+            "xunlei_cgi_duration_seconds",
+            "Lifetime of UI CGI processes.",
+        );
+        let slots = self.slots.lock().unwrap();
+        out.metric(
+            "xunlei_cgi_in_flight",
+            "gauge",
+            "UI CGI processes currently running.",
+            self.processes.lock().unwrap().len(),
+        );
+        out.metric(
+            "xunlei_cgi_queued",
+            "gauge",
+            "UI requests waiting for a CGI slot.",
+            slots.queued,
+        );
+        out.metric(
+            "xunlei_cgi_rejected_total",
+            "counter",
+            "UI requests rejected because the CGI queue was full.",
+            self.metrics.rejected.load(Ordering::Relaxed),
+        );
+        out.metric(
+            "xunlei_cgi_timeouts_total",
+            "counter",
+            "UI CGI processes killed after the timeout.",
+            self.metrics.timed_out.load(Ordering::Relaxed),
+        );
+        out.metric(
+            "xunlei_cgi_failures_total",
+            "counter",
+            "UI CGI processes that failed to spawn or answered an invalid response.",
+            self.metrics.failed.load(Ordering::Relaxed),
+        );
+    }
+
+    fn kill(&self, signal: libc::c_int) {
+        for pid in self.processes.lock().unwrap().keys() {
+            unsafe { libc::killpg(*pid, signal) };
+        }
+    }
+
+    // SIGTERM outstanding CGI processes, escalating to SIGKILL after the grace period
+    pub fn stop(&self, grace: Duration) {
+        if self.processes.lock().unwrap().is_empty() {
+            return;
+        }
+        self.kill(libc::SIGTERM);
+        let deadline = Instant::now() + grace;
+        while self.processes.lock().unwrap().is_empty().not() && Instant::now() < deadline {
+            std::thread::sleep(STOP_POLL_INTERVAL);
+        }
+        self.kill(libc::SIGKILL);
+    }
+}
+
+// Copy a request body into an unlinked temporary file, returning it rewound with its length.
+// `None` when the body exceeds MAX_SPOOL_SIZE.
+fn spool(body: impl Read) -> std::io::Result<Option<(std::fs::File, u64)>> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // Only readable by the launcher, the file never gets a name where O_TMPFILE is supported
+    let dir = std::env::temp_dir();
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true).write(true).mode(0o600);
+    let mut file = match options.clone().custom_flags(libc::O_TMPFILE).open(&dir) {
+        Ok(file) => file,
+        Err(_) => {
+            let path = dir.join(format!("xunlei-cgi-{:016x}", rand::random::<u64>()));
+            let file = options.create_new(true).open(&path)?;
+            std::fs::remove_file(&path)?;
+            file
+        }
+    };
+    let length = std::io::copy(&mut body.take(MAX_SPOOL_SIZE + 1), &mut file)?;
+    if length > MAX_SPOOL_SIZE {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(Some((file, length)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTEN: &str = "192.0.2.1:5055";
+    const PEER: &str = "198.51.100.7:40000";
+
+    fn request(method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> rouille::Request {
+        rouille::Request::fake_http_from(
+            PEER.parse().unwrap(),
+            method,
+            url,
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body.to_vec(),
+        )
+    }
+
+    fn env(request: &rouille::Request, script_name: &str) -> BTreeMap<String, String> {
+        CgiEnvBuilder::new(request, LISTEN.parse().unwrap())
+            .script_name(script_name)
+            .script_filename("/var/packages/pan-xunlei-com/target/xunlei-pan-cli-web")
+            .build()
+            .into_iter()
+            .collect()
+    }
+
+    // Variables of every request plus `vars`
+    fn expected(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        [
+            (
+                "SERVER_SOFTWARE",
+                concat!("xunlei/", env!("CARGO_PKG_VERSION")),
+            ),
+            ("SERVER_PROTOCOL", "HTTP/1.1"),
+            ("GATEWAY_INTERFACE", "CGI/1.1"),
+            ("SERVER_ADDR", "192.0.2.1"),
+            ("SERVER_PORT", "5055"),
+            ("REMOTE_ADDR", "198.51.100.7"),
+            ("REMOTE_HOST", "198.51.100.7"),
+            ("REMOTE_PORT", "40000"),
+            (
+                "SCRIPT_FILENAME",
+                "/var/packages/pan-xunlei-com/target/xunlei-pan-cli-web",
+            ),
+        ]
+        .iter()
+        .chain(vars)
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn get_env() {
+        let request = request(
+            "GET",
+            "/webman/3rdparty/pan-xunlei-com/index.cgi/device/now?x=1&y=2",
+            &[
+                ("Host", "nas.local:5055"),
+                ("Accept-Language", "zh-CN"),
+                ("X-Device-Id", "abc"),
+                ("X-Device-Id", "def"),
+            ],
+            b"",
+        );
+        assert_eq!(
+            env(&request, "/webman/3rdparty/pan-xunlei-com/index.cgi"),
+            expected(&[
+                ("SERVER_NAME", "nas.local"),
+                ("REQUEST_METHOD", "GET"),
+                (
+                    "REQUEST_URI",
+                    "/webman/3rdparty/pan-xunlei-com/index.cgi/device/now?x=1&y=2"
+                ),
+                ("QUERY_STRING", "x=1&y=2"),
+                ("SCRIPT_NAME", "/webman/3rdparty/pan-xunlei-com/index.cgi"),
+                ("PATH_INFO", "/device/now"),
+                ("HTTP_HOST", "nas.local:5055"),
+                ("HTTP_ACCEPT_LANGUAGE", "zh-CN"),
+                ("HTTP_X_DEVICE_ID", "abc, def"),
+            ])
+        );
+    }
+
+    #[test]
+    fn post_env() {
+        let request = request(
+            "POST",
+            "/webman/3rdparty/pan-xunlei-com/index.cgi/drive/v1/files",
+            &[
+                ("Host", "[2001:db8::1]:5055"),
+                ("Content-Type", "application/json"),
+                ("Content-Length", "2"),
+                ("Connection", "keep-alive, X-Hop"),
+                ("X-Hop", "1"),
+                ("Keep-Alive", "timeout=5"),
+                ("Proxy", "http://evil.example:8080"),
+                ("Proxy-Authorization", "Basic Zm9vOmJhcg=="),
+                ("TE", "trailers"),
+                ("Upgrade", "websocket"),
+                ("Cookie", "id=1"),
+            ],
+            b"{}",
+        );
+        assert_eq!(
+            env(&request, "/webman/3rdparty/pan-xunlei-com/index.cgi"),
+            expected(&[
+                ("SERVER_NAME", "2001:db8::1"),
+                ("REQUEST_METHOD", "POST"),
+                (
+                    "REQUEST_URI",
+                    "/webman/3rdparty/pan-xunlei-com/index.cgi/drive/v1/files"
+                ),
+                ("QUERY_STRING", ""),
+                ("SCRIPT_NAME", "/webman/3rdparty/pan-xunlei-com/index.cgi"),
+                ("PATH_INFO", "/drive/v1/files"),
+                ("CONTENT_TYPE", "application/json"),
+                ("CONTENT_LENGTH", "2"),
+                ("HTTP_HOST", "[2001:db8::1]:5055"),
+                ("HTTP_COOKIE", "id=1"),
+            ])
+        );
+    }
+
+    #[test]
+    fn path_info_split() {
+        let cases = [
+            // Request path, script name, PATH_INFO
+            ("/webman/index.cgi", "/webman/index.cgi", ""),
+            ("/webman/index.cgi/", "/webman/index.cgi", "/"),
+            ("/webman/index.cgi/a/b", "/webman/index.cgi", "/a/b"),
+            ("/other/a", "/webman/index.cgi", "/other/a"),
+            ("/a/b", "", "/a/b"),
+        ];
+        for (url, script_name, path_info) in cases {
+            let request = request("GET", url, &[], b"");
+            let env = env(&request, script_name);
+            assert_eq!(env["PATH_INFO"], path_info, "{}", url);
+            assert_eq!(env["SCRIPT_NAME"], script_name, "{}", url);
+        }
+    }
+
+    fn parse(output: &[u8]) -> Result<CgiHeaders, String> {
+        CgiHeaders::parse(&mut std::io::BufReader::new(output))
+    }
+
+    #[test]
+    fn headers_status_and_location() {
+        let headers =
+            parse(b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nbody").unwrap();
+        assert_eq!(headers.status, Some(404));
+        assert_eq!(headers.location, None);
+        assert_eq!(
+            headers.headers,
+            vec![("Content-Type".into(), "text/plain".into())]
+        );
+
+        let headers = parse(b"Location: /webman/login.cgi\n\n").unwrap();
+        assert_eq!(headers.status, None);
+        assert_eq!(headers.location.as_deref(), Some("/webman/login.cgi"));
+        assert!(headers.headers.is_empty());
+
+        let headers = parse(b"status: 302\r\nlocation: http://example.com/\r\n\r\n").unwrap();
+        assert_eq!(headers.status, Some(302));
+        assert_eq!(headers.location.as_deref(), Some("http://example.com/"));
+
+        for output in [
+            &b"Status: 4\r\n\r\n"[..],
+            b"Status: 600\r\n\r\n",
+            b"Status: abc\r\n\r\n",
+            b"garbage\r\n\r\n",
+            b"Bad Name: x\r\n\r\n",
+            b"\r\n",
+            b"Content-Type: text/plain\r\n",
+            b"",
+        ] {
+            assert!(
+                parse(output).is_err(),
+                "{}",
+                String::from_utf8_lossy(output)
+            );
+        }
+    }
+
+    #[test]
+    fn headers_size_cap() {
+        let value = "x".repeat(1024);
+        let mut output = Vec::new();
+        while (output.len() as u64) < MAX_HEADER_SIZE - 2048 {
+            output.extend_from_slice(format!("X-Filler: {}\r\n", value).as_bytes());
+        }
+        let mut within = output.clone();
+        within.extend_from_slice(b"\r\n");
+        assert!(parse(&within).is_ok());
+
+        output.extend_from_slice(format!("X-Filler: {}{}\r\n\r\n", value, value).as_bytes());
+        let error = parse(&output).err().unwrap();
+        assert!(error.contains("exceeds"), "{}", error);
+    }
+
+    #[test]
+    fn spool_body() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (mut file, length) = spool(&b"a=1&b=2"[..]).unwrap().unwrap();
+        assert_eq!(length, 7);
+        assert_eq!(file.metadata().unwrap().mode() & 0o777, 0o600);
+        assert_eq!(file.metadata().unwrap().nlink(), 0);
+        let mut body = String::new();
+        file.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "a=1&b=2");
+
+        let oversize = std::io::repeat(b'x').take(MAX_SPOOL_SIZE + 1);
+        assert!(spool(oversize).unwrap().is_none());
+    }
+}
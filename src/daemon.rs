@@ -0,0 +1,182 @@
+use anyhow::Context;
+use std::{
+    fs::File,
+    io::Write,
+    ops::Not,
+    os::unix::prelude::{AsRawFd, OpenOptionsExt},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::standard;
+
+// Poll interval while waiting for the daemon to exit
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Pid file locked for the lifetime of the daemon, so that a second daemon can't start even
+// when the pid has been reused
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    fn create(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            if parent.exists().not() {
+                standard::create_dir_all(parent, 0o755)?;
+            }
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .open(path)
+            .context(format!("Failed to open pid file: {}", path.display()))?;
+        // The lock is shared with the forked children and released once the daemon exits
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                match read_pid(path) {
+                    Some(pid) => anyhow::bail!("xunlei is already running, pid: {}", pid),
+                    None => anyhow::bail!("xunlei is already running"),
+                }
+            }
+            return Err(e).context(format!("Failed to lock pid file: {}", path.display()));
+        }
+        if read_pid(path).is_some() {
+            log::warn!("[Daemon] Replacing stale pid file: {}", path.display());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        let mut file = &self.file;
+        file.set_len(0)
+            .and_then(|_| file.write_all(format!("{}\n", std::process::id()).as_bytes()))
+            .context(format!("Failed to write pid file: {}", self.path.display()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Only remove the pid file if it still belongs to this process
+        if read_pid(&self.path) == Some(std::process::id() as libc::pid_t) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<libc::pid_t> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// Whether the pid belongs to a running `xunlei launch` process
+fn running(pid: libc::pid_t) -> bool {
+    if pid <= 0 || unsafe { libc::kill(pid, 0) } != 0 {
+        return false;
+    }
+    match std::fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => cmdline.split(|b| *b == 0).any(|arg| arg == b"launch"),
+        Err(_) => false,
+    }
+}
+
+fn fork() -> anyhow::Result<libc::pid_t> {
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        anyhow::bail!("fork error: {}", std::io::Error::last_os_error());
+    }
+    Ok(pid)
+}
+
+// Point stdin to /dev/null and stdout/stderr to the log file
+pub(crate) fn redirect(log_file: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = log_file.parent() {
+        if parent.exists().not() {
+            standard::create_dir_all(parent, 0o755)?;
+        }
+    }
+    let null = std::fs::File::open("/dev/null")?;
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .context(format!("Failed to open log file: {}", log_file.display()))?;
+    unsafe {
+        if libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) < 0
+            || libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO) < 0
+            || libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO) < 0
+        {
+            anyhow::bail!(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Detach from the terminal with a double fork, must be called before any thread is spawned
+pub fn daemonize(pidfile: &Path, log_file: &Path) -> anyhow::Result<PidFile> {
+    let pidfile = PidFile::create(pidfile)?;
+
+    let pid = fork()?;
+    if pid > 0 {
+        // Wait for the intermediate process so that the daemon is running when we return
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        log::info!("[Daemon] xunlei is running in the background");
+        std::process::exit(0);
+    }
+
+    if unsafe { libc::setsid() } < 0 {
+        anyhow::bail!("setsid error: {}", std::io::Error::last_os_error());
+    }
+    // Don't keep the directory we were started from busy, paths have been made absolute
+    std::env::set_current_dir("/")?;
+    if fork()? > 0 {
+        unsafe { libc::_exit(0) };
+    }
+
+    redirect(log_file)?;
+    pidfile.write()?;
+    Ok(pidfile)
+}
+
+// Stop the daemon recorded in the pid file
+pub fn stop(pidfile: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let pid = match read_pid(pidfile) {
+        Some(pid) => pid,
+        None => {
+            log::warn!("[Daemon] xunlei is not running");
+            return Ok(());
+        }
+    };
+    if running(pid).not() {
+        log::warn!("[Daemon] Removing stale pid file: {}", pidfile.display());
+        std::fs::remove_file(pidfile)?;
+        return Ok(());
+    }
+
+    log::info!("[Daemon] Stopping xunlei, pid: {}", pid);
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let deadline = Instant::now() + timeout;
+    while running(pid) {
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "xunlei (pid: {}) did not exit within {}s",
+                pid,
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(STOP_POLL_INTERVAL);
+    }
+    if pidfile.exists() {
+        std::fs::remove_file(pidfile)?;
+    }
+    log::info!("[Daemon] xunlei has been stopped");
+    Ok(())
+}
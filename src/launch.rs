@@ -1,36 +1,669 @@
 use anyhow::Context;
 use signal_hook::iterator::Signals;
 
-use crate::{standard, Config, Running};
+use crate::{
+    accesslog::{AccessLog, AccessLogFormat},
+    acl::{self, Acl, Cidr},
+    auth::{self, Auth},
+    basepath::BasePath,
+    cgi::{self, CgiEnvBuilder, CgiExecutor, CgiLimits, CgiOutput},
+    libc_asset::{LibcMode, Loader},
+    limits::{Cgroup, Limits},
+    listener::{self, ListenAddr, ListenSpec, RelayPorts, RelaySocket},
+    logrotate::{LogRotation, LogRotator},
+    metrics::{Exposition, ProcessHistory, ProcessStats},
+    namespace::Namespace,
+    proxy,
+    runas::RunAs,
+    standard,
+    tls::Tls,
+    Config, Running,
+};
 use std::{
     collections::HashMap,
-    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     ops::Not,
-    os::unix::prelude::PermissionsExt,
+    os::unix::prelude::{CommandExt, PermissionsExt},
     path::{Path, PathBuf},
     process::Stdio,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+        mpsc, Arc, Mutex, OnceLock, RwLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+// Upper bound of the exponential restart backoff
+const BACKEND_MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+// A backend that stays up this long is considered healthy again
+const BACKEND_STABLE_UPTIME: Duration = Duration::from_secs(300);
+// Poll interval while waiting for processes to exit
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// A stopped UI server closes its socket on its accept thread shortly after, a rebind of the
+// same address is retried this many times
+const REBIND_ATTEMPTS: usize = 10;
+// Maximum number of CGI local redirects followed for one request
+const MAX_LOCAL_REDIRECTS: usize = 8;
+// DSM API error code of a missing or wrong SynoToken
+const SYNO_ERROR_INVALID_TOKEN: u32 = 119;
+// Interval between log rotation checks
+const LOG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
 pub struct XunleiLauncher {
-    host: std::net::IpAddr,
-    port: u16,
+    listeners: Vec<Listener>,
     download_path: PathBuf,
     config_path: PathBuf,
+    envs: Vec<(String, String)>,
+    max_restarts: u32,
+    restart_delay: Duration,
+    stop_timeout: Duration,
+    log_rotation: LogRotation,
+    cgi_limits: CgiLimits,
+    http_redirect_port: Option<u16>,
+    session_ttl: Duration,
+    isolate: bool,
+    replace_loader: bool,
+    libc: LibcMode,
+    limits: Limits,
+    run_as: RunAs,
+    trusted_proxies: Vec<Cidr>,
+    base_path: BasePath,
+    metrics: bool,
+    metrics_token: Option<String>,
+    access_log: Option<PathBuf>,
+    access_log_format: AccessLogFormat,
+    // Launcher log file when running as a daemon
+    log_file: Option<PathBuf>,
+    // Command line configuration, the configuration file is applied on top of it on reload
+    cli: Config,
+}
+
+// A UI listener with its TLS and ACL settings resolved
+#[derive(Clone, PartialEq)]
+struct Listener {
+    addr: ListenAddr,
+    tls: Option<Tls>,
+    acl: Acl,
+    proxy_protocol: bool,
+}
+
+// UI listeners, the HTTP to HTTPS redirect listener included
+type UiServer = Vec<(JoinHandle<()>, mpsc::Sender<()>)>;
+
+enum Event {
+    Signal(i32),
+    BackendExited,
+}
+
+// Backend settings that may change on reload
+#[derive(Clone, PartialEq)]
+struct BackendConfig {
+    envs: HashMap<String, String>,
+    // Bundled glibc loader the Xunlei programs run through, None for the host libc
+    loader: Option<Loader>,
+    run_as: RunAs,
+    limits: Limits,
+    // Directories handed over to the backend account
+    config_path: PathBuf,
+    download_path: PathBuf,
+    max_restarts: u32,
+    restart_delay: Duration,
+}
+
+// State shared between the signal handler and the backend supervisor
+struct BackendState {
+    pid: AtomicI32,
+    shutdown: AtomicBool,
+    restart: AtomicBool,
+    config: RwLock<BackendConfig>,
+    // Mount namespace of the backend and CGI processes when isolated
+    namespace: Option<Arc<Namespace>>,
+    // Cgroup enforcing the memory and CPU limits of the backend, created once they are first set
+    cgroup: Mutex<Option<Cgroup>>,
+    // Number of times the backend has been respawned
+    restarts: AtomicU64,
+    // Start time of the running backend
+    started: Mutex<Option<Instant>>,
+    // Keeps the resource counters of the backend processes monotonic across exits and restarts
+    history: Mutex<ProcessHistory>,
 }
 
-impl From<Config> for XunleiLauncher {
-    fn from(config: Config) -> Self {
+impl BackendState {
+    fn new(config: BackendConfig, namespace: Option<Arc<Namespace>>) -> Self {
         Self {
-            host: config.host,
-            port: config.port,
+            pid: AtomicI32::new(0),
+            shutdown: AtomicBool::new(false),
+            restart: AtomicBool::new(false),
+            config: RwLock::new(config),
+            namespace,
+            cgroup: Mutex::new(None),
+            restarts: AtomicU64::new(0),
+            started: Mutex::new(None),
+            history: Mutex::new(ProcessHistory::default()),
+        }
+    }
+
+    fn config(&self) -> BackendConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    // Create the backend cgroup the first time memory or CPU limits are set, at startup or reload
+    fn prepare_cgroup(&self, limits: &Limits) {
+        let mut cgroup = self.cgroup.lock().unwrap();
+        if cgroup.is_none() && limits.needs_cgroup() {
+            *cgroup = Cgroup::create();
+        }
+    }
+
+    fn alive(&self) -> bool {
+        let pid = self.pid.load(Ordering::SeqCst);
+        pid > 0 && unsafe { libc::kill(pid, 0) } == 0
+    }
+
+    fn render_metrics(&self, out: &mut Exposition) {
+        let up = self.alive();
+        out.metric(
+            "xunlei_backend_up",
+            "gauge",
+            "Whether the Xunlei backend is running.",
+            up as u8,
+        );
+        out.metric(
+            "xunlei_backend_restarts_total",
+            "counter",
+            "Number of times the Xunlei backend has been restarted.",
+            self.restarts.load(Ordering::Relaxed),
+        );
+        let uptime = self
+            .started
+            .lock()
+            .unwrap()
+            .map(|started| started.elapsed());
+        out.metric(
+            "xunlei_backend_uptime_seconds",
+            "gauge",
+            "Seconds since the running Xunlei backend was started.",
+            uptime.unwrap_or_default().as_secs_f64(),
+        );
+
+        let pgid = match up {
+            true => self.pid.load(Ordering::SeqCst),
+            false => 0,
+        };
+        let stats = ProcessStats::of_group(pgid, &mut self.history.lock().unwrap());
+        out.metric(
+            "xunlei_backend_processes",
+            "gauge",
+            "Processes in the Xunlei backend process group.",
+            stats.processes,
+        );
+        out.metric(
+            "xunlei_backend_resident_memory_bytes",
+            "gauge",
+            "Resident memory of the Xunlei backend processes.",
+            stats.rss_bytes,
+        );
+        out.metric(
+            "xunlei_backend_cpu_seconds_total",
+            "counter",
+            "User and system CPU time of the Xunlei backend processes.",
+            stats.cpu_seconds,
+        );
+        out.metric(
+            "xunlei_backend_read_bytes_total",
+            "counter",
+            "Bytes read from storage by the Xunlei backend processes.",
+            stats.read_bytes,
+        );
+        out.metric(
+            "xunlei_backend_written_bytes_total",
+            "counter",
+            "Bytes written to storage by the Xunlei backend processes.",
+            stats.write_bytes,
+        );
+    }
+
+    fn terminate(&self) {
+        let pid = self.pid.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe { libc::killpg(pid, libc::SIGTERM) };
+        }
+    }
+
+    // SIGTERM the backend process group, escalating to SIGKILL after the grace period
+    fn stop(&self, grace: Duration) {
+        let pid = self.pid.load(Ordering::SeqCst);
+        if pid <= 0 {
+            return;
+        }
+        unsafe { libc::killpg(pid, libc::SIGTERM) };
+        let deadline = Instant::now() + grace;
+        while self.pid.load(Ordering::SeqCst) == pid && Instant::now() < deadline {
+            std::thread::sleep(STOP_POLL_INTERVAL);
+        }
+        if self.pid.load(Ordering::SeqCst) == pid {
+            log::warn!(
+                "[XunleiLauncher] Backend did not exit within {}s, sending SIGKILL",
+                grace.as_secs()
+            );
+            unsafe { libc::killpg(pid, libc::SIGKILL) };
+        }
+    }
+}
+
+// Routes UI requests, everything that is not handled by the launcher goes to the CGI
+struct UiHandler {
+    // Address the server is bound to, known once the listener has been created
+    listen: Arc<OnceLock<SocketAddr>>,
+    https: bool,
+    acl: Acl,
+    // Relayed connections of a unix socket or PROXY protocol listener, other loopback peers are rejected
+    relay: Option<RelayPorts>,
+    // Proxies whose forwarded client addresses are believed
+    trusted_proxies: Arc<Vec<Cidr>>,
+    run_as: RunAs,
+    envs: HashMap<String, String>,
+    loader: Option<Loader>,
+    state: Arc<BackendState>,
+    cgi: Arc<CgiExecutor>,
+    auth: Arc<Auth>,
+    access_log: Arc<AccessLog>,
+    base_path: BasePath,
+    metrics: bool,
+    // Bearer token required to scrape /metrics, independent of the UI login
+    metrics_token: Option<String>,
+}
+
+impl UiHandler {
+    // Route the request relative to the base path and move the response URLs under it
+    fn dispatch(&self, request: &rouille::Request, client: SocketAddr) -> rouille::Response {
+        // Probes and scrapers talk to the launcher directly rather than through the reverse proxy
+        if self.base_path.is_root()
+            || matches!(request.url().as_str(), "/healthz" | "/readyz" | "/metrics")
+        {
+            return self.handle(request, client, 0);
+        }
+        match self.base_path.strip(request) {
+            Some(stripped) if stripped.url().is_empty() => {
+                rouille::Response::redirect_307(format!("{}/", self.base_path))
+            }
+            Some(stripped) => self.base_path.rewrite(self.handle(&stripped, client, 0)),
+            None if request.url() == "/" => {
+                rouille::Response::redirect_307(format!("{}/", self.base_path))
+            }
+            None => rouille::Response::empty_404(),
+        }
+    }
+
+    // Address of the client: the peer of a relayed connection or the source announced by its
+    // PROXY protocol header, then the address forwarded by trusted proxies. None for connections
+    // that bypassed the relay, their peer isn't one of the relay's own sockets.
+    fn client(&self, request: &rouille::Request) -> Option<SocketAddr> {
+        let mut peer = *request.remote_addr();
+        if let Some(ports) = &self.relay {
+            if let Some(remote) = ports.lock().unwrap().get(&peer)? {
+                peer = *remote;
+            }
+        }
+        Some(proxy::client_addr(request, peer, &self.trusted_proxies))
+    }
+
+    fn handle(
+        &self,
+        request: &rouille::Request,
+        client: SocketAddr,
+        redirects: usize,
+    ) -> rouille::Response {
+        if self.acl.permits(client.ip()).not() {
+            return XunleiLauncher::denied(request, client.ip());
+        }
+
+        // Probes and the login page stay reachable without a session
+        let public = matches!(
+            request.url().as_str(),
+            "/healthz" | "/readyz" | "/metrics" | "/login" | "/logout"
+        );
+        if public.not() && self.auth.authenticated(request).not() {
+            return match request.method() {
+                "GET" | "HEAD" => rouille::Response::redirect_303("/login"),
+                _ => rouille::Response::json(&serde_json::json!({ "error": "unauthorized" }))
+                    .with_status_code(401),
+            };
+        }
+
+        rouille::router!(request,
+            (GET) ["/healthz"] => {
+                rouille::Response::json(&serde_json::json!({
+                    "status": "ok",
+                    "cgi": self.cgi.stats(),
+                }))
+            },
+            (GET) ["/readyz"] => {
+                let backend = self.state.alive();
+                let sock = XunleiLauncher::sock_ready(standard::SOCK_FILE);
+                let launcher_sock = XunleiLauncher::sock_ready(standard::LAUNCHER_SOCK);
+                let ready = backend && sock && launcher_sock;
+                rouille::Response::json(&serde_json::json!({
+                    "status": if ready { "ready" } else { "unavailable" },
+                    "backend": backend,
+                    "sock": sock,
+                    "launcher_sock": launcher_sock,
+                }))
+                .with_status_code(if ready { 200 } else { 503 })
+            },
+            (GET) ["/metrics"] => {
+                self.metrics(request)
+            },
+            (GET) ["/login"] => {
+                match self.auth.enabled() {
+                    true => auth::login_page(None),
+                    false => rouille::Response::redirect_303(standard::SYNOPKG_WEB_UI_HOME),
+                }
+            },
+            (POST) ["/login"] => {
+                if self.auth.enabled().not() {
+                    return rouille::Response::redirect_303(standard::SYNOPKG_WEB_UI_HOME);
+                }
+                let password = match rouille::post_input!(request, { password: String }) {
+                    Ok(input) => input.password,
+                    Err(_) => return auth::login_page(None).with_status_code(400),
+                };
+                match self.auth.login(&password) {
+                    Ok(Some(token)) => rouille::Response::redirect_303(standard::SYNOPKG_WEB_UI_HOME)
+                        .with_additional_header(
+                            "Set-Cookie",
+                            auth::session_cookie(&token, self.auth.ttl(), self.https),
+                        ),
+                    Ok(None) => {
+                        log::warn!("[Auth] Failed login from {}", client.ip());
+                        auth::login_page(Some("Incorrect password")).with_status_code(401)
+                    }
+                    Err(e) => {
+                        log::error!("[Auth] Login failed: {:?}", e);
+                        rouille::Response::text("Internal Server Error").with_status_code(500)
+                    }
+                }
+            },
+            (GET) ["/logout"] => {
+                self.auth.logout(request);
+                rouille::Response::redirect_303("/login").with_additional_header(
+                    "Set-Cookie",
+                    auth::session_cookie("", Duration::ZERO, self.https),
+                )
+            },
+            (GET) ["/webman/login.cgi"] => {
+                self.syno_login(request)
+            },
+            (POST) ["/webman/login.cgi"] => {
+                self.syno_login(request)
+            },
+            (GET) ["/"] => {
+                rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
+            },
+            (GET) ["/webman/"] => {
+                rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
+            },
+            (GET) ["/webman/3rdparty/pan-xunlei-com"] => {
+                rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
+             },
+            _ => {
+                let write = matches!(request.method(), "POST" | "PUT" | "PATCH" | "DELETE");
+                if write && self.auth.check_syno_token(request).not() {
+                    log::warn!(
+                        "[Auth] Rejected {} {} from {}: invalid SynoToken",
+                        request.method(),
+                        request.raw_url(),
+                        client.ip()
+                    );
+                    return rouille::Response::json(&serde_json::json!({
+                        "error": { "code": SYNO_ERROR_INVALID_TOKEN },
+                        "success": false,
+                    }))
+                    .with_status_code(403);
+                }
+                let listen = self.listen.get().copied();
+                let listen = listen.unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, 0).into());
+                let env = CgiEnvBuilder::new(request, listen)
+                    .remote_addr(client)
+                    .script_name(standard::SYNOPKG_WEB_UI_HOME.trim_end_matches('/'))
+                    .script_filename(standard::SYNOPKG_CLI_WEB)
+                    .https(self.https)
+                    .build();
+                let mut cmd = XunleiLauncher::command(standard::SYNOPKG_CLI_WEB, self.loader.as_ref());
+                if let Some(namespace) = &self.state.namespace {
+                    if let Err(e) = namespace.enter(&mut cmd, standard::SYNOPKG_PKGDEST) {
+                        log::error!("[XunleiLauncher] {:?}", e);
+                        return rouille::Response::text("Internal Server Error")
+                            .with_status_code(500);
+                    }
+                }
+                self.run_as.apply(&mut cmd);
+                cmd.current_dir(standard::SYNOPKG_PKGDEST)
+                    .envs(&self.envs)
+                    .envs(env)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .stdin(Stdio::piped());
+
+                match self.cgi.execute(request, cmd) {
+                    CgiOutput::Response(response) => response,
+                    CgiOutput::LocalRedirect(location) => {
+                        self.redirect(request, client, &location, redirects)
+                    }
+                }
+            }
+        )
+    }
+
+    fn metrics(&self, request: &rouille::Request) -> rouille::Response {
+        if self.metrics.not() {
+            return rouille::Response::empty_404();
+        }
+        if let Some(token) = &self.metrics_token {
+            let bearer = request
+                .header("Authorization")
+                .and_then(|value| value.strip_prefix("Bearer "));
+            if bearer.map(|bearer| auth::token_eq(bearer.trim(), token)) != Some(true) {
+                return rouille::Response::text("Unauthorized")
+                    .with_status_code(401)
+                    .with_additional_header("WWW-Authenticate", "Bearer");
+            }
+        }
+        let mut out = Exposition::default();
+        self.state.render_metrics(&mut out);
+        self.cgi.render_metrics(&mut out);
+        out.into_response()
+    }
+
+    // DSM login.cgi: hand out the SynoToken of the session, opening one when no password is set
+    fn syno_login(&self, request: &rouille::Request) -> rouille::Response {
+        let (cookie, syno_token) = match self.auth.syno_token(request) {
+            Some(syno_token) => (None, syno_token),
+            None => {
+                let (token, syno_token) = self.auth.open_anonymous();
+                (Some(token), syno_token)
+            }
+        };
+        let response = rouille::Response::json(&serde_json::json!({
+            "SynoToken": syno_token,
+            "result": "success",
+            "success": true,
+        }));
+        match cookie {
+            Some(token) => response.with_additional_header(
+                "Set-Cookie",
+                auth::session_cookie(&token, self.auth.ttl(), self.https),
+            ),
+            None => response,
+        }
+    }
+
+    // Serve a CGI local redirect as a new GET request for the location
+    fn redirect(
+        &self,
+        request: &rouille::Request,
+        client: SocketAddr,
+        location: &str,
+        redirects: usize,
+    ) -> rouille::Response {
+        if redirects >= MAX_LOCAL_REDIRECTS {
+            log::error!(
+                "[XunleiLauncher] Too many CGI local redirects for {}",
+                request.raw_url()
+            );
+            return rouille::Response::text("Bad Gateway").with_status_code(502);
+        }
+        let headers = request
+            .headers()
+            .filter(|(name, _)| {
+                name.eq_ignore_ascii_case("Content-Length").not()
+                    && name.eq_ignore_ascii_case("Content-Type").not()
+                    && name.eq_ignore_ascii_case("Transfer-Encoding").not()
+            })
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let redirected = rouille::Request::fake_http_from(
+            *request.remote_addr(),
+            "GET",
+            location,
+            headers,
+            Vec::new(),
+        );
+        self.handle(&redirected, client, redirects + 1)
+    }
+}
+
+impl TryFrom<Config> for XunleiLauncher {
+    type Error = anyhow::Error;
+
+    fn try_from(cli: Config) -> anyhow::Result<Self> {
+        let config = cli.load()?;
+        let metrics_token = match (config.metrics_token, &config.metrics_token_file) {
+            (Some(token), _) => Some(token),
+            (None, Some(path)) => Some(auth::read_secret(path)?),
+            (None, None) => None,
+        };
+        let tls = match (config.tls_cert, config.tls_key) {
+            (Some(cert), Some(key)) => Some(Tls::new(cert, key)),
+            (None, None) if config.tls => Some(Tls::self_signed(&config.config_path)),
+            (None, None) => None,
+            _ => anyhow::bail!("tls_cert and tls_key must be set together"),
+        };
+        let acl = Acl {
+            allow: config.allow,
+            deny: config.deny,
+        };
+        let specs = match config.listen.is_empty() {
+            true => vec![ListenSpec::new(ListenAddr::Tcp(SocketAddr::new(
+                config.host.unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                config.port,
+            )))],
+            false => config.listen,
+        };
+        let listeners = specs
+            .into_iter()
+            .map(|spec| {
+                let tls = match (spec.cert, spec.key, spec.tls) {
+                    (_, _, Some(false)) => None,
+                    (Some(cert), Some(key), _) => Some(Tls::new(cert, key)),
+                    (_, _, Some(true)) => Some(
+                        tls.clone()
+                            .unwrap_or_else(|| Tls::self_signed(&config.config_path)),
+                    ),
+                    _ => tls.clone(),
+                };
+                // A listener allow list replaces the global one, deny entries add up
+                let mut acl = acl.clone();
+                if spec.allow.is_empty().not() {
+                    acl.allow = spec.allow;
+                }
+                acl.deny.extend(spec.deny);
+                Listener {
+                    addr: spec.addr,
+                    tls,
+                    acl,
+                    proxy_protocol: spec.proxy_protocol,
+                }
+            })
+            .collect::<Vec<_>>();
+        for listener in &listeners {
+            if listener.proxy_protocol
+                && matches!(listener.addr, ListenAddr::Tcp(_))
+                && config.trusted_proxy.is_empty()
+            {
+                anyhow::bail!(format!(
+                    "listener {} uses proxy-protocol but no trusted_proxy is set",
+                    listener.addr
+                ));
+            }
+        }
+        if let Some(port) = config.http_redirect_port {
+            match XunleiLauncher::https_listener(&listeners) {
+                None => anyhow::bail!("http_redirect_port requires a TLS listener"),
+                Some((addr, _)) if addr.port() == port => {
+                    anyhow::bail!("http_redirect_port must differ from port {}", port)
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(Self {
+            listeners,
             download_path: config.download_path,
             config_path: config.config_path,
-        }
+            envs: config.envs,
+            max_restarts: config.max_restarts,
+            restart_delay: Duration::from_secs(config.restart_delay),
+            stop_timeout: Duration::from_secs(config.stop_timeout),
+            log_rotation: LogRotation {
+                max_size: config.log_max_size * 1024 * 1024,
+                max_age: Duration::from_secs(config.log_max_age * 24 * 60 * 60),
+                keep: config.log_keep,
+            },
+            cgi_limits: CgiLimits {
+                max_concurrency: config.cgi_max_concurrency,
+                max_queue: config.cgi_max_queue,
+                timeout: Duration::from_secs(config.cgi_timeout),
+            },
+            http_redirect_port: config.http_redirect_port,
+            session_ttl: Duration::from_secs(config.session_ttl * 60 * 60),
+            isolate: config.isolate,
+            replace_loader: config.replace_loader,
+            libc: config.libc,
+            limits: Limits {
+                memory: config.memory_limit,
+                cpu: config.cpu_limit,
+                nofile: config.nofile_limit,
+            },
+            run_as: RunAs::resolve(
+                config.user.as_deref(),
+                config.group.as_deref(),
+                config.umask,
+            )?,
+            trusted_proxies: config.trusted_proxy,
+            base_path: config.base_path,
+            metrics: config.metrics || metrics_token.is_some(),
+            metrics_token,
+            access_log: config.access_log,
+            access_log_format: config.access_log_format,
+            log_file: None,
+            cli,
+        })
     }
 }
 
 impl XunleiLauncher {
-    fn run_backend(envs: HashMap<String, String>) -> anyhow::Result<std::process::Child> {
+    pub fn with_log_file(mut self, log_file: PathBuf) -> Self {
+        self.log_file = Some(log_file);
+        self
+    }
+
+    fn run_backend(
+        config: &BackendConfig,
+        namespace: Option<&Arc<Namespace>>,
+        cgroup: Option<&Cgroup>,
+    ) -> anyhow::Result<std::process::Child> {
         log::info!("[XunleiLauncher] Start Xunlei Engine");
         let var_path = Path::new(standard::SYNOPKG_VAR);
         if var_path.exists().not() {
@@ -39,131 +672,455 @@ impl XunleiLauncher {
                 format!("Failed to set permissions: {} -- 755", var_path.display()),
             )?;
         }
-        let child_process = std::process::Command::new(standard::LAUNCHER_EXE)
+        if config.run_as.is_default().not() {
+            XunleiLauncher::prepare_dirs(config)?;
+            log::info!("[XunleiLauncher] Running backend as {}", config.run_as);
+        }
+        let mut cmd = XunleiLauncher::command(standard::LAUNCHER_EXE, config.loader.as_ref());
+        if let Some(namespace) = namespace {
+            namespace.enter(&mut cmd, standard::SYNOPKG_PKGDEST)?;
+        }
+        if config.limits.is_default().not() {
+            log::info!("[XunleiLauncher] Backend limits: {}", config.limits);
+        }
+        config.limits.apply(&mut cmd, cgroup)?;
+        config.run_as.apply(&mut cmd);
+        let child_process = cmd
             .args([
                 format!("-launcher_listen={}", standard::LAUNCHER_SOCK),
                 format!("-pid={}", standard::PID_FILE),
                 format!("-logfile={}", standard::LAUNCH_LOG_FILE),
             ])
             .current_dir(standard::SYNOPKG_PKGDEST)
-            .envs(&envs)
-            // Join the parent process group by default
+            .envs(&config.envs)
+            // Run in a dedicated process group so that shutdown reaches every engine process
+            .process_group(0)
             .spawn()
-            .expect("failed to spawn child process");
+            .context(format!("Failed to spawn: {}", standard::LAUNCHER_EXE))?;
         let child_pid = child_process.id() as libc::pid_t;
         log::info!("[XunleiLauncher] Backend pid: {}", child_pid);
         Ok(child_process)
     }
 
-    fn run_ui(host: String, port: u16, envs: HashMap<String, String>) {
-        log::info!("[XunleiLauncher] Start Xunlei Engine UI");
-        rouille::start_server(format!("{}:{}", host, port), move |request| {
-            rouille::router!(request,
-                (GET) ["/webman/login.cgi"] => {
-                    rouille::Response::json(&String::from(r#"{"SynoToken", ""}"#))
-                    .with_additional_header("Content-Type", "application/json; charset=utf-8")
-                    .with_status_code(200)
-                 },
-                (GET) ["/"] => {
-                    rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
-                },
-                (GET) ["/webman/"] => {
-                    rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
-                },
-                (GET) ["/webman/3rdparty/pan-xunlei-com"] => {
-                    rouille::Response::redirect_307(standard::SYNOPKG_WEB_UI_HOME)
-                 },
-                _ => {
-                    let mut cmd = std::process::Command::new(standard::SYNOPKG_CLI_WEB);
-                    cmd.current_dir(standard::SYNOPKG_PKGDEST);
-                    cmd.envs(&envs)
-                    .env("SERVER_SOFTWARE", "rust")
-                    .env("SERVER_PROTOCOL", "HTTP/1.1")
-                    .env("HTTP_HOST", &request.remote_addr().to_string())
-                    .env("GATEWAY_INTERFACE", "CGI/1.1")
-                    .env("REQUEST_METHOD", request.method())
-                    .env("QUERY_STRING", request.raw_query_string())
-                    .env("REQUEST_URI", request.raw_url())
-                    .env("PATH_INFO", &request.url())
-                    .env("SCRIPT_NAME", ".")
-                    .env("SCRIPT_FILENAME", &request.url())
-                    .env("SERVER_PORT", port.to_string())
-                    .env("REMOTE_ADDR", request.remote_addr().to_string())
-                    .env("SERVER_NAME", request.remote_addr().to_string())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::inherit())
-                    .stdin(Stdio::piped());
+    // Config directory of the backend. One running as another user gets its own below the
+    // launcher's, which holds the UI password and TLS key.
+    fn data_path(config_path: &Path, run_as: &RunAs) -> PathBuf {
+        match run_as.switches_account() {
+            true => config_path.join(standard::BACKEND_DATA_DIR),
+            false => config_path.to_path_buf(),
+        }
+    }
 
-                    for ele in request.headers() {
-                        let k = ele.0.to_uppercase();
-                        let v = ele.1;
-                        if k == "PROXY" {
-                            continue
-                        }
-                        if v.is_empty().not() {
-                            cmd.env(format!("HTTP_{}", k), v);
-                        }
-                    }
+    // Give the backend account the directories it writes to. The package, the launcher's
+    // secrets in the config directory and its pid and log files stay with root.
+    fn prepare_dirs(config: &BackendConfig) -> anyhow::Result<()> {
+        let skip = [
+            PathBuf::from(standard::LAUNCH_PID_FILE),
+            PathBuf::from(standard::DAEMON_LOG_FILE),
+        ];
+        let run_as = &config.run_as;
+        run_as.chown(Path::new(standard::SYNOPKG_VAR), true, &skip)?;
+        let data_path = XunleiLauncher::data_path(&config.config_path, run_as);
+        if data_path.exists().not() {
+            standard::create_dir_all(&data_path, 0o755)?;
+        }
+        run_as.chown(&data_path, true, &skip)?;
+        // Downloads may be huge, only the directory itself is handed over
+        run_as.chown(&config.download_path, false, &skip)
+    }
 
-                    if request.header("Content-Type").unwrap_or_default().is_empty().not() {
-                        cmd.env(
-                            "CONTENT_TYPE",
-                            request.header("Content-Type").unwrap(),
-                        );
+    // Keep the backend running, restarting it with exponential backoff when it exits
+    fn supervise(state: Arc<BackendState>, shutdown: mpsc::Receiver<()>) -> anyhow::Result<()> {
+        let mut restarts = 0;
+        let mut delay = state.config().restart_delay;
+        loop {
+            let config = state.config();
+            let started = Instant::now();
+            let spawned = XunleiLauncher::run_backend(
+                &config,
+                state.namespace.as_ref(),
+                state.cgroup.lock().unwrap().as_ref(),
+            );
+            match spawned {
+                Ok(mut child) => {
+                    state.pid.store(child.id() as i32, Ordering::SeqCst);
+                    *state.started.lock().unwrap() = Some(Instant::now());
+                    // The shutdown signal may have arrived before the pid was published
+                    if state.shutdown.load(Ordering::SeqCst) {
+                        state.terminate();
                     }
-
-                    if request.header("content-type").unwrap_or_default().is_empty().not() {
-                        cmd.env(
-                            "CONTENT_TYPE",
-                            request.header("content-type").unwrap(),
-                        );
+                    let status = child.wait()?;
+                    state.pid.store(0, Ordering::SeqCst);
+                    *state.started.lock().unwrap() = None;
+                    // Kill leftover engine processes so that a restart finds the sockets free
+                    let pgid = child.id() as libc::pid_t;
+                    if unsafe { libc::killpg(pgid, 0) } == 0 {
+                        unsafe { libc::killpg(pgid, libc::SIGKILL) };
                     }
-
-                    if request.header("Content-Length").unwrap_or_default().is_empty().not() {
-                        cmd.env(
-                            "CONTENT_LENGTH",
-                            request.header("Content-Length").unwrap(),
-                        );
+                    if state.shutdown.load(Ordering::SeqCst) {
+                        log::info!("[XunleiLauncher] The backend service has been terminated");
+                        return Ok(());
+                    }
+                    if state.restart.swap(false, Ordering::SeqCst) {
+                        log::info!("[XunleiLauncher] Restarting backend to apply configuration");
+                        state.restarts.fetch_add(1, Ordering::Relaxed);
+                        continue;
                     }
+                    log::error!("[XunleiLauncher] Backend exited unexpectedly: {}", status);
+                }
+                Err(e) => log::error!("[XunleiLauncher] Failed to start backend: {:?}", e),
+            }
 
-                    let mut child = cmd.spawn().unwrap();
+            if started.elapsed() >= BACKEND_STABLE_UPTIME {
+                restarts = 0;
+                delay = config.restart_delay;
+            }
+            if restarts >= config.max_restarts {
+                anyhow::bail!(
+                    "[XunleiLauncher] Backend restart limit reached ({}), giving up",
+                    config.max_restarts
+                );
+            }
+            restarts += 1;
+            log::warn!(
+                "[XunleiLauncher] Restarting backend in {}s ({}/{})",
+                delay.as_secs(),
+                restarts,
+                config.max_restarts
+            );
+            if shutdown.recv_timeout(delay).is_ok() {
+                return Ok(());
+            }
+            state.restarts.fetch_add(1, Ordering::Relaxed);
+            delay = std::cmp::min(delay * 2, BACKEND_MAX_RESTART_DELAY);
+        }
+    }
 
-                    if let Some(mut body) = request.data() {
-                        std::io::copy(&mut body, child.stdin.as_mut().unwrap()).unwrap();
-                    }
+    // Check whether a `unix://` socket of the backend accepts connections
+    fn sock_ready(sock: &str) -> bool {
+        let path = sock.trim_start_matches("unix://");
+        std::os::unix::net::UnixStream::connect(path).is_ok()
+    }
 
-                    {
-                        let mut stdout = std::io::BufReader::new(child.stdout.unwrap());
+    fn run_ui(
+        &self,
+        backend: &BackendConfig,
+        state: Arc<BackendState>,
+        cgi: Arc<CgiExecutor>,
+        auth: Arc<Auth>,
+        access_log: Arc<AccessLog>,
+    ) -> anyhow::Result<UiServer> {
+        log::info!("[XunleiLauncher] Start Xunlei Engine UI");
+        let trusted_proxies = Arc::new(self.trusted_proxies.clone());
+        let mut servers = Vec::new();
+        for listener in &self.listeners {
+            let handler = UiHandler {
+                listen: Arc::new(OnceLock::new()),
+                https: listener.tls.is_some(),
+                acl: listener.acl.clone(),
+                relay: None,
+                trusted_proxies: trusted_proxies.clone(),
+                run_as: self.run_as.clone(),
+                envs: backend.envs.clone(),
+                loader: backend.loader.clone(),
+                state: state.clone(),
+                cgi: cgi.clone(),
+                auth: auth.clone(),
+                access_log: access_log.clone(),
+                base_path: self.base_path.clone(),
+                metrics: self.metrics,
+                metrics_token: self.metrics_token.clone(),
+            };
+            match XunleiLauncher::serve(listener, handler) {
+                Ok(server) => servers.extend(server),
+                Err(e) => {
+                    XunleiLauncher::stop_ui(&mut Some(servers));
+                    return Err(e);
+                }
+            }
+        }
 
-                        let mut headers = Vec::new();
-                        let mut status_code = 200;
-                        for header in std::io::BufRead::lines(stdout.by_ref()) {
-                            let header = header.unwrap();
-                            if header.is_empty() {
-                                break;
-                            }
+        if let Some(redirect_port) = self.http_redirect_port {
+            match self.run_redirect(redirect_port) {
+                Ok(server) => servers.push(server),
+                Err(e) => {
+                    XunleiLauncher::stop_ui(&mut Some(servers));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(servers)
+    }
 
-                            let (header, val) = header.split_once(':').unwrap();
-                            let val = &val[1..];
+    // Start one listener, unix sockets and PROXY protocol listeners are served by a loopback
+    // server behind a relay
+    fn serve(listener: &Listener, mut handler: UiHandler) -> anyhow::Result<UiServer> {
+        let relayed = listener.proxy_protocol || matches!(listener.addr, ListenAddr::Unix(_));
+        let bind = match &listener.addr {
+            ListenAddr::Tcp(addr) if relayed.not() => *addr,
+            _ => {
+                handler.relay = Some(RelayPorts::default());
+                (Ipv4Addr::LOCALHOST, 0).into()
+            }
+        };
+        let listen = handler.listen.clone();
+        let relay = handler.relay.clone();
+        let trusted_proxies = handler.trusted_proxies.clone();
+        let handler = Arc::new(handler);
+        let server =
+            XunleiLauncher::bind(bind, listener.tls.as_ref(), handler.clone()).or_else(|e| {
+                match bind.ip() {
+                    // Fall back to IPv4 on hosts without IPv6
+                    IpAddr::V6(ip) if ip.is_unspecified() => {
+                        log::warn!("[XunleiLauncher] {}, falling back to 0.0.0.0", e);
+                        let bind = (Ipv4Addr::UNSPECIFIED, bind.port()).into();
+                        XunleiLauncher::bind(bind, listener.tls.as_ref(), handler)
+                    }
+                    _ => Err(e),
+                }
+            })?;
+        let target = server.server_addr();
+        let server = server.stoppable();
 
-                            if header == "Status" {
-                                status_code = val[0..3]
-                                    .parse()
-                                    .expect("Status returned by CGI program is invalid");
-                            } else {
-                                headers.push((header.to_owned().into(), val.to_owned().into()));
-                            }
-                        }
-                        rouille::Response {
-                            status_code,
-                            headers,
-                            data: rouille::ResponseBody::from_reader(stdout),
-                            upgrade: None,
-                        }
+        let (addr, relay) = match relay {
+            Some(ports) => {
+                let proxy_protocol = listener.proxy_protocol.then_some(trusted_proxies);
+                let relay = RelaySocket::bind(&listener.addr).and_then(|socket| {
+                    let addr = socket.local_addr()?;
+                    Ok((
+                        addr,
+                        listener::relay(socket, target, ports, proxy_protocol)?,
+                    ))
+                });
+                match relay {
+                    Ok((addr, relay)) => (addr, Some(relay)),
+                    Err(e) => {
+                        XunleiLauncher::stop_ui(&mut Some(vec![server]));
+                        return Err(e);
                     }
                 }
-            )
+            }
+            None => (ListenAddr::Tcp(target), None),
+        };
+        let _ = listen.set(match &addr {
+            ListenAddr::Tcp(addr) => *addr,
+            ListenAddr::Unix(_) => target,
         });
+        log::info!(
+            "[XunleiLauncher] UI listening on {}{}{}",
+            addr,
+            if listener.tls.is_some() {
+                " (https)"
+            } else {
+                ""
+            },
+            if listener.proxy_protocol {
+                " (proxy protocol)"
+            } else {
+                ""
+            }
+        );
+        Ok(std::iter::once(server).chain(relay).collect())
+    }
+
+    fn bind(
+        addr: SocketAddr,
+        tls: Option<&Tls>,
+        handler: Arc<UiHandler>,
+    ) -> anyhow::Result<rouille::Server<impl Fn(&rouille::Request) -> rouille::Response>> {
+        let handler = move |request: &rouille::Request| {
+            let started = Instant::now();
+            let (client, response) = match handler.client(request) {
+                Some(client) => (client, handler.dispatch(request, client)),
+                None => {
+                    let peer = *request.remote_addr();
+                    (peer, XunleiLauncher::denied(request, peer.ip()))
+                }
+            };
+            handler
+                .access_log
+                .record(request, client.ip(), response, started)
+        };
+        let tls = tls.map(|tls| tls.load(addr.ip())).transpose()?;
+        let mut attempts = 1;
+        loop {
+            let server = match &tls {
+                Some((cert, key)) => {
+                    rouille::Server::new_ssl(addr, handler.clone(), cert.clone(), key.clone())
+                }
+                None => rouille::Server::new(addr, handler.clone()),
+            };
+            match server {
+                Err(e)
+                    if attempts < REBIND_ATTEMPTS
+                        && e.downcast_ref::<std::io::Error>().map(|e| e.kind())
+                            == Some(std::io::ErrorKind::AddrInUse) =>
+                {
+                    attempts += 1;
+                    std::thread::sleep(STOP_POLL_INTERVAL);
+                }
+                server => {
+                    return server.map_err(|e| {
+                        anyhow::anyhow!("Failed to start UI server on {}: {}", addr, e)
+                    })
+                }
+            }
+        }
+    }
+
+    // First TCP listener serving HTTPS, the target of the HTTP redirect
+    fn https_listener(listeners: &[Listener]) -> Option<(SocketAddr, &Acl)> {
+        listeners.iter().find_map(|listener| match listener.addr {
+            ListenAddr::Tcp(addr) if listener.tls.is_some() => Some((addr, &listener.acl)),
+            _ => None,
+        })
+    }
+
+    // Plain HTTP listener that redirects to the HTTPS listener
+    fn run_redirect(
+        &self,
+        redirect_port: u16,
+    ) -> anyhow::Result<(JoinHandle<()>, mpsc::Sender<()>)> {
+        let (addr, acl) = XunleiLauncher::https_listener(&self.listeners)
+            .context("http_redirect_port requires a TLS listener")?;
+        let (acl, port) = (acl.clone(), addr.port());
+        let trusted_proxies = self.trusted_proxies.clone();
+        let server = rouille::Server::new((addr.ip(), redirect_port), move |request| {
+            let client = proxy::client_addr(request, *request.remote_addr(), &trusted_proxies);
+            if acl.permits(client.ip()).not() {
+                return XunleiLauncher::denied(request, client.ip());
+            }
+            let name = match request.header("Host").map(cgi::host_name) {
+                Some(name) if name.is_empty().not() => name.to_string(),
+                _ => addr.ip().to_string(),
+            };
+            let authority = match name.contains(':') {
+                true => format!("[{}]:{}", name, port),
+                false => format!("{}:{}", name, port),
+            };
+            rouille::Response::redirect_301(format!("https://{}{}", authority, request.raw_url()))
+        })
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to start HTTP redirect server on {}:{}: {}",
+                addr.ip(),
+                redirect_port,
+                e
+            )
+        })?;
+        Ok(server.stoppable())
+    }
+
+    fn denied(request: &rouille::Request, client: IpAddr) -> rouille::Response {
+        log::warn!(
+            "[XunleiLauncher] Denied {} {} from {}",
+            request.method(),
+            request.raw_url(),
+            acl::canonical(client)
+        );
+        rouille::Response::text("Forbidden").with_status_code(403)
+    }
+
+    fn reload(&self) -> anyhow::Result<XunleiLauncher> {
+        let mut next = XunleiLauncher::try_from(self.cli.clone())?;
+        next.log_file = self.log_file.clone();
+        Ok(next)
+    }
+
+    fn backend_config(&self) -> anyhow::Result<BackendConfig> {
+        let loader = Loader::resolve(self.libc)?;
+        let mut envs = self.envs()?;
+        if let Some(loader) = &loader {
+            loader.env(&mut envs);
+        }
+        Ok(BackendConfig {
+            envs,
+            loader,
+            run_as: self.run_as.clone(),
+            limits: self.limits,
+            config_path: self.config_path.clone(),
+            download_path: self.download_path.clone(),
+            max_restarts: self.max_restarts,
+            restart_delay: self.restart_delay,
+        })
+    }
+
+    // Apply a reloaded configuration, restarting only the services affected by the change. The
+    // UI is rebound first as it is the step that can fail, nothing is applied when it does.
+    fn reconfigure(
+        &self,
+        next: &XunleiLauncher,
+        state: &Arc<BackendState>,
+        cgi: &Arc<CgiExecutor>,
+        auth: &Arc<Auth>,
+        access_log: &Arc<AccessLog>,
+        ui: &mut Option<UiServer>,
+    ) -> anyhow::Result<()> {
+        if next.isolate != self.isolate {
+            log::warn!("[XunleiLauncher] Changing isolate requires restarting the launcher");
+        }
+        if next.replace_loader != self.replace_loader {
+            log::warn!("[XunleiLauncher] Changing replace_loader requires restarting the launcher");
+        }
+        let backend = next.backend_config()?;
+        let current = state.config();
+        let envs_changed = backend.envs != current.envs || backend.loader != current.loader;
+        let run_as_changed = backend.run_as != current.run_as;
+        let limits_changed = backend.limits != current.limits;
+
+        if envs_changed
+            || run_as_changed
+            || next.listeners != self.listeners
+            || next.http_redirect_port != self.http_redirect_port
+            || next.trusted_proxies != self.trusted_proxies
+            || next.base_path != self.base_path
+            || next.metrics != self.metrics
+            || next.metrics_token != self.metrics_token
+        {
+            log::info!("[XunleiLauncher] Rebinding UI listeners");
+            // The new listeners may take over the addresses of the current ones
+            XunleiLauncher::stop_ui(ui);
+            match next.run_ui(
+                &backend,
+                state.clone(),
+                cgi.clone(),
+                auth.clone(),
+                access_log.clone(),
+            ) {
+                Ok(server) => *ui = Some(server),
+                Err(e) => {
+                    // Fall back to the previous listener so the UI stays reachable
+                    *ui = Some(self.run_ui(
+                        &current,
+                        state.clone(),
+                        cgi.clone(),
+                        auth.clone(),
+                        access_log.clone(),
+                    )?);
+                    return Err(e);
+                }
+            }
+        }
+
+        state.prepare_cgroup(&backend.limits);
+        *state.config.write().unwrap() = backend;
+        if (envs_changed || run_as_changed || limits_changed)
+            && state.pid.load(Ordering::SeqCst) > 0
+        {
+            log::info!("[XunleiLauncher] Environment changed, restarting backend");
+            state.restart.store(true, Ordering::SeqCst);
+            state.stop(next.stop_timeout);
+        }
+        Ok(())
+    }
+
+    fn stop_ui(ui: &mut Option<UiServer>) {
+        for (handle, stop) in ui.take().unwrap_or_default() {
+            let _ = stop.send(());
+            handle
+                .join()
+                .expect("[XunleiLauncher] Failed to join UI thread");
+        }
     }
 
     fn envs(&self) -> anyhow::Result<HashMap<String, String>> {
@@ -181,11 +1138,9 @@ impl XunleiLauncher {
                 standard::SYNOPKG_DSM_VERSION_BUILD
             ),
         );
-        envs.insert(String::from("HOME"), self.config_path.display().to_string());
-        envs.insert(
-            String::from("ConfigPath"),
-            self.config_path.display().to_string(),
-        );
+        let data_path = XunleiLauncher::data_path(&self.config_path, &self.run_as);
+        envs.insert(String::from("HOME"), data_path.display().to_string());
+        envs.insert(String::from("ConfigPath"), data_path.display().to_string());
         envs.insert(
             String::from("DownloadPATH"),
             self.download_path.display().to_string(),
@@ -229,59 +1184,178 @@ impl XunleiLauncher {
         );
         envs.insert(String::from("INST_LOG"), String::from(standard::INST_LOG));
         envs.insert(String::from("GIN_MODE"), String::from("release"));
-
-        #[cfg(all(target_os = "linux", target_env = "musl"))]
-        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
-        crate::libc_asset::ld_env(&mut envs)?;
+        envs.extend(self.envs.iter().cloned());
         Ok(envs)
     }
+
+    // Command for one of the Xunlei programs, run through the bundled glibc loader if any
+    fn command(program: &str, loader: Option<&Loader>) -> std::process::Command {
+        match loader {
+            Some(loader) => loader.command(program),
+            None => std::process::Command::new(program),
+        }
+    }
 }
 
 impl Running for XunleiLauncher {
     fn launch(&self) -> anyhow::Result<()> {
-        use std::thread::{Builder, JoinHandle};
+        use std::thread::Builder;
 
         let mut signals = Signals::new([
             signal_hook::consts::SIGINT,
             signal_hook::consts::SIGHUP,
             signal_hook::consts::SIGTERM,
+            signal_hook::consts::SIGUSR1,
         ])?;
 
-        let ui_envs = self.envs()?;
-        let backend_envs = ui_envs.clone();
-        let backend_thread: JoinHandle<_> = Builder::new()
-            .name("backend".to_string())
+        // A launcher killed while the loader was linked left it behind
+        crate::libc_asset::restore_loader()?;
+        let backend = self.backend_config()?;
+        let ui_backend = backend.clone();
+        // Dropped after everything set up below, the backend included, on every exit path
+        let _linked_loader = match &backend.loader {
+            Some(loader) if self.replace_loader => Some(loader.link()?),
+            Some(loader) if loader.required && self.isolate.not() => {
+                log::warn!(
+                    "[XunleiLauncher] There is no glibc loader at {}, programs that Xunlei spawns by itself may fail without --isolate or --replace-loader",
+                    loader.interpreter.display()
+                );
+                None
+            }
+            _ => None,
+        };
+        let namespace = match self.isolate {
+            true => Some(Namespace::create(backend.loader.clone())?),
+            false => None,
+        };
+        let state = Arc::new(BackendState::new(backend, namespace));
+        state.prepare_cgroup(&self.limits);
+        let cgi = CgiExecutor::new(self.cgi_limits);
+        let auth = Auth::new(&self.config_path, self.session_ttl);
+        let access_log = AccessLog::new(self.access_log.as_deref(), self.access_log_format)?;
+        let (shutdown_tx, shutdown_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+
+        let signal_tx = event_tx.clone();
+        Builder::new()
+            .name("signal".to_string())
             .spawn(move || {
-                let backend_process = XunleiLauncher::run_backend(backend_envs)
-                    .expect("[XunleiLauncher] An error occurred executing the backend process");
                 for signal in signals.forever() {
-                    match signal {
-                        signal_hook::consts::SIGINT
-                        | signal_hook::consts::SIGHUP
-                        | signal_hook::consts::SIGTERM => {
-                            unsafe { libc::kill(backend_process.id() as i32, libc::SIGTERM) };
-                            log::info!("[XunleiLauncher] The backend service has been terminated");
-                            break;
-                        }
-                        _ => {
-                            log::warn!("[XunleiLauncher] The system receives an unprocessed signal")
-                        }
+                    if signal_tx.send(Event::Signal(signal)).is_err() {
+                        break;
                     }
                 }
             })
-            .expect("[XunleiLauncher] Failed to start backend thread");
+            .expect("[XunleiLauncher] Failed to start signal thread");
+
+        let mut log_rotator = LogRotator::new(self.log_rotation);
+        log_rotator.watch(standard::LOG_FILE);
+        log_rotator.watch(standard::LAUNCH_LOG_FILE);
+        if let Some(log_file) = &self.log_file {
+            log_rotator.watch(log_file);
+        }
+        if let Some(access_log) = &self.access_log {
+            log_rotator.watch(access_log);
+        }
+        let log_rotator = Arc::new(Mutex::new(log_rotator));
+        let rotator = log_rotator.clone();
+        Builder::new()
+            .name("logrotate".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(LOG_CHECK_INTERVAL);
+                rotator.lock().unwrap().check();
+            })
+            .expect("[XunleiLauncher] Failed to start logrotate thread");
 
-        let host = self.host.to_string();
-        let port = self.port;
         // run webui service
-        std::thread::spawn(move || {
-            XunleiLauncher::run_ui(host, port, ui_envs);
-        });
+        let mut ui = Some(self.run_ui(
+            &ui_backend,
+            state.clone(),
+            cgi.clone(),
+            auth.clone(),
+            access_log.clone(),
+        )?);
 
-        backend_thread
+        let backend_state = state.clone();
+        let backend_thread: JoinHandle<_> = Builder::new()
+            .name("backend".to_string())
+            .spawn(move || {
+                let result = XunleiLauncher::supervise(backend_state, shutdown_rx);
+                let _ = event_tx.send(Event::BackendExited);
+                result
+            })
+            .expect("[XunleiLauncher] Failed to start backend thread");
+
+        let mut current = self.clone();
+        while let Ok(event) = event_rx.recv() {
+            match event {
+                Event::Signal(signal_hook::consts::SIGHUP) => {
+                    log::info!("[XunleiLauncher] Received SIGHUP, reloading configuration");
+                    match current.reload().and_then(|next| {
+                        current.reconfigure(&next, &state, &cgi, &auth, &access_log, &mut ui)?;
+                        Ok(next)
+                    }) {
+                        Ok(next) => {
+                            log_rotator.lock().unwrap().set_rotation(next.log_rotation);
+                            cgi.set_limits(next.cgi_limits);
+                            auth.configure(&next.config_path, next.session_ttl);
+                            if next.access_log != current.access_log
+                                || next.access_log_format != current.access_log_format
+                            {
+                                if let Err(e) = access_log
+                                    .configure(next.access_log.as_deref(), next.access_log_format)
+                                {
+                                    log::error!("[XunleiLauncher] {:?}", e);
+                                }
+                                if let Some(path) = &next.access_log {
+                                    log_rotator.lock().unwrap().watch(path);
+                                }
+                            }
+                            current = next;
+                            log::info!("[XunleiLauncher] Configuration reloaded");
+                        }
+                        Err(e) => log::error!("[XunleiLauncher] Reload failed: {:?}", e),
+                    }
+                }
+                Event::Signal(signal_hook::consts::SIGUSR1) => {
+                    if let Err(e) = access_log.reopen() {
+                        log::error!("[XunleiLauncher] Failed to reopen access log: {:?}", e);
+                    }
+                    if let Some(log_file) = &current.log_file {
+                        match crate::daemon::redirect(log_file) {
+                            Ok(()) => log::info!("[XunleiLauncher] Reopened log file"),
+                            Err(e) => log::error!("[XunleiLauncher] Failed to reopen log: {:?}", e),
+                        }
+                    }
+                }
+                Event::Signal(signal_hook::consts::SIGINT)
+                | Event::Signal(signal_hook::consts::SIGTERM) => {
+                    log::info!("[XunleiLauncher] Received signal, shutting down");
+                    state.shutdown.store(true, Ordering::SeqCst);
+                    let _ = shutdown_tx.send(());
+                    state.stop(current.stop_timeout);
+                    break;
+                }
+                Event::Signal(_) => {
+                    log::warn!("[XunleiLauncher] The system receives an unprocessed signal")
+                }
+                Event::BackendExited => break,
+            }
+        }
+
+        let result = backend_thread
             .join()
             .expect("[XunleiLauncher] Failed to join thread");
 
+        XunleiLauncher::stop_ui(&mut ui);
+        log::info!("[XunleiLauncher] The UI service has been stopped");
+        cgi.stop(current.stop_timeout);
+
+        if let Some(cgroup) = state.cgroup.lock().unwrap().as_ref() {
+            cgroup.remove();
+        }
+
+        result?;
         log::info!("[XunleiLauncher] All services have been complete");
         Ok(())
     }
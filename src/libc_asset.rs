@@ -1,3 +1,17 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    ops::Not,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::standard;
+
 #[cfg(target_arch = "x86_64")]
 #[derive(rust_embed::RustEmbed)]
 #[folder = "libc/x86_64/"]
@@ -8,46 +22,519 @@ struct Asset;
 #[folder = "libc/aarch64/"]
 struct Asset;
 
-pub(crate) fn ld_env(envs: &mut std::collections::HashMap<String, String>) -> anyhow::Result<()> {
-    use crate::standard;
-    use anyhow::Context;
-    use std::ffi::CString;
-    use std::path::Path;
+#[cfg(target_arch = "x86_64")]
+const LD: &str = "ld-linux-x86-64.so.2";
+#[cfg(target_arch = "aarch64")]
+const LD: &str = "ld-linux-aarch64.so.1";
+
+// Suffix of the system loader moved aside while the bundled one is linked in its place
+const BACKUP_SUFFIX: &str = ".xunlei-backup";
 
-    #[cfg(target_arch = "x86_64")]
-    const LD: &str = "ld-linux-x86-64.so.2";
-    #[cfg(target_arch = "aarch64")]
-    const LD: &str = "ld-linux-aarch64.so.1";
+// Suffix of a library being written, renamed over the installed one once complete
+const TEMP_SUFFIX: &str = ".tmp";
 
-    let libc_path = std::path::Path::new(standard::SYNOPKG_LIB);
+// ELF program header holding the path of the program interpreter
+const PT_INTERP: u32 = 3;
+
+// Xunlei programs whose interpreter decides which glibc is needed
+const PROGRAMS: [&str; 2] = [standard::LAUNCHER_EXE, standard::SYNOPKG_CLI_WEB];
+
+// Which glibc the Xunlei programs run with
+#[derive(Clone, Copy, PartialEq)]
+pub enum LibcMode {
+    // The host glibc when it can run the programs, else the bundled one
+    Auto,
+    System,
+    Bundled,
+}
+
+impl LibcMode {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" => Ok(LibcMode::Auto),
+            "system" => Ok(LibcMode::System),
+            "bundled" => Ok(LibcMode::Bundled),
+            _ => anyhow::bail!(format!("`{}` isn't a libc mode (auto, system, bundled)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for LibcMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibcMode::Auto => write!(f, "auto"),
+            LibcMode::System => write!(f, "system"),
+            LibcMode::Bundled => write!(f, "bundled"),
+        }
+    }
+}
+
+// Deployed bundled glibc loader and the path the Xunlei programs expect their loader at
+#[derive(Clone, PartialEq)]
+pub struct Loader {
+    pub path: PathBuf,
+    pub interpreter: PathBuf,
+    // The host has no glibc loader at the interpreter path, as on musl systems
+    pub required: bool,
+}
+
+impl Loader {
+    // Deploy the bundled glibc when `mode` asks for it or the host can't run the Xunlei
+    // programs, None when they run with the host libc
+    pub fn resolve(mode: LibcMode) -> anyhow::Result<Option<Self>> {
+        let interpreters = PROGRAMS
+            .iter()
+            .map(|program| Ok((Path::new(*program), interpreter(Path::new(program))?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Statically linked programs and scripts need no loader at all
+        let dynamic = interpreters
+            .iter()
+            .filter_map(|(program, interpreter)| Some((*program, interpreter.as_deref()?)))
+            .collect::<Vec<_>>();
+        // A glibc too old or missing libraries is worked around with --library-path alone, only
+        // a host without any glibc loader there needs one at the interpreter path
+        let required = dynamic
+            .iter()
+            .any(|(_, interpreter)| is_glibc_loader(interpreter).not());
+        let bundled = match mode {
+            LibcMode::System => false,
+            LibcMode::Bundled => true,
+            LibcMode::Auto => dynamic
+                .iter()
+                .any(|(program, interpreter)| host_glibc_runs(interpreter, program).not()),
+        };
+        if bundled.not() {
+            return Ok(None);
+        }
+
+        let path = deploy()?;
+        let interpreter = interpreters
+            .into_iter()
+            .find_map(|(_, interpreter)| interpreter)
+            .unwrap_or_else(|| Path::new(standard::SYS_LIB).join(LD));
+        log::info!(
+            "[XunleiLauncher] Xunlei runs with the bundled glibc ({})",
+            path.display()
+        );
+        Ok(Some(Self {
+            path,
+            interpreter,
+            required,
+        }))
+    }
+
+    // Run a Xunlei program through the bundled loader, the host loader isn't involved
+    pub fn command(&self, program: &str) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.arg("--library-path")
+            .arg(standard::SYNOPKG_LIB)
+            .arg(program);
+        cmd
+    }
+
+    // Programs that the engine spawns by itself find the bundled libraries too
+    pub fn env(&self, envs: &mut HashMap<String, String>) {
+        envs.insert(
+            String::from("LD_LIBRARY_PATH"),
+            standard::SYNOPKG_LIB.to_string(),
+        );
+    }
+
+    // Link the bundled loader at the interpreter path for the programs that the engine spawns
+    // by itself, whatever was there is moved aside and put back when the guard is dropped or
+    // by restore_loader on the next start. The loader of a host glibc is never replaced.
+    pub fn link(&self) -> anyhow::Result<LinkedLoader> {
+        let (sys_ld, syno_ld) = (&self.interpreter, &self.path);
+        if is_linked(sys_ld) {
+            return Ok(LinkedLoader(()));
+        }
+        let backup = backup_path(sys_ld);
+        if std::fs::symlink_metadata(sys_ld).is_ok() {
+            if is_glibc_loader(sys_ld) {
+                anyhow::bail!(format!(
+                    "{} is the loader of the host glibc, refusing to replace it",
+                    sys_ld.display()
+                ));
+            }
+            if std::fs::symlink_metadata(&backup).is_ok() {
+                anyhow::bail!(format!(
+                    "{} already exists, restore or remove it before replacing {}",
+                    backup.display(),
+                    sys_ld.display()
+                ));
+            }
+            std::fs::rename(sys_ld, &backup).context(format!(
+                "Failed to back up {} to {}",
+                sys_ld.display(),
+                backup.display()
+            ))?;
+            log::info!(
+                "[XunleiLauncher] Backed up {} to {}",
+                sys_ld.display(),
+                backup.display()
+            );
+        } else if let Some(parent) = sys_ld.parent() {
+            standard::create_dir_all(parent, 0o755)?;
+        }
+        std::os::unix::fs::symlink(syno_ld, sys_ld).context(format!(
+            "Failed to link {} to {}",
+            sys_ld.display(),
+            syno_ld.display()
+        ))?;
+        log::info!(
+            "[XunleiLauncher] Linked {} to {}",
+            sys_ld.display(),
+            syno_ld.display()
+        );
+        Ok(LinkedLoader(()))
+    }
+}
+
+// Restores the host loader when dropped, on whichever path the launcher exits
+pub struct LinkedLoader(());
+
+impl Drop for LinkedLoader {
+    fn drop(&mut self) {
+        if let Err(e) = restore_loader() {
+            log::error!("[XunleiLauncher] {:?}", e);
+        }
+    }
+}
+
+// Undo Loader::link, also for a launcher that was killed before it could. Loaders that aren't
+// links into the package are left alone.
+pub fn restore_loader() -> anyhow::Result<()> {
+    let mut candidates = PROGRAMS
+        .iter()
+        .filter_map(|program| interpreter(Path::new(program)).ok().flatten())
+        .collect::<Vec<_>>();
+    candidates.push(Path::new(standard::SYS_LIB).join(LD));
+
+    for sys_ld in candidates {
+        let backup = backup_path(&sys_ld);
+        let linked = is_linked(&sys_ld);
+        if linked {
+            std::fs::remove_file(&sys_ld)
+                .context(format!("Failed to remove {}", sys_ld.display()))?;
+        }
+        if std::fs::symlink_metadata(&backup).is_err() {
+            if linked {
+                log::info!("[XunleiLauncher] Removed {}", sys_ld.display());
+            }
+            continue;
+        }
+        if std::fs::symlink_metadata(&sys_ld).is_ok() {
+            log::warn!(
+                "[XunleiLauncher] {} exists, leaving {} alone",
+                sys_ld.display(),
+                backup.display()
+            );
+            continue;
+        }
+        std::fs::rename(&backup, &sys_ld).context(format!(
+            "Failed to restore {} from {}",
+            sys_ld.display(),
+            backup.display()
+        ))?;
+        log::info!("[XunleiLauncher] Restored {}", sys_ld.display());
+    }
+    Ok(())
+}
+
+// Write the bundled glibc into the package and return its loader. Installed libraries that
+// don't match the embedded ones, truncated or left by another version, are replaced.
+fn deploy() -> anyhow::Result<PathBuf> {
+    let libc_path = Path::new(standard::SYNOPKG_LIB);
     if !libc_path.exists() {
-        std::fs::create_dir(&libc_path)?;
+        std::fs::create_dir(libc_path)?;
     }
     for filename in Asset::iter()
         .map(|v| v.into_owned())
         .collect::<Vec<String>>()
     {
         let file = Asset::get(&filename).context("Failed to get bin asset")?;
-        let target_file = libc_path.join(filename);
-        if !target_file.exists() {
-            standard::write_file(&target_file, file.data, 0o755)?;
+        let hash = file.metadata.sha256_hash();
+        if Sha256::digest(&file.data).as_slice() != hash {
+            anyhow::bail!(format!(
+                "Bundled {} doesn't match its checksum, the xunlei binary is corrupt",
+                filename
+            ));
+        }
+        let target_file = libc_path.join(&filename);
+        let repair = match file_hash(&target_file) {
+            Ok(installed) if installed == hash => continue,
+            Ok(_) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => {
+                return Err(e).context(format!("Failed to read {}", target_file.display()));
+            }
+        };
+        if repair {
+            log::warn!(
+                "[XunleiLauncher] {} is corrupt or outdated, rewriting it",
+                target_file.display()
+            );
+        }
+        write_atomic(&target_file, &file.data).context(match repair {
+            true => format!("{} is corrupt and can't be repaired", target_file.display()),
+            false => format!("Failed to install {}", filename),
+        })?;
+        if file_hash(&target_file).ok() != Some(hash) {
+            anyhow::bail!(format!(
+                "{} is corrupt, it doesn't match the bundled {} after being rewritten",
+                target_file.display(),
+                filename
+            ));
+        }
+    }
+    Ok(libc_path.join(LD))
+}
+
+fn file_hash(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+// Replace `path` in one step, running programs keep the library they have mapped
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+    let result = (|| -> anyhow::Result<()> {
+        standard::write_file(&temp, std::borrow::Cow::Borrowed(data), 0o755)?;
+        File::open(&temp)?.sync_all()?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.context(format!("Failed to write {}", path.display()))
+}
+
+// PT_INTERP of an ELF program, None for static programs and files that aren't ELF
+fn interpreter(program: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let mut file = File::open(program).context(format!(
+        "Failed to open {}, run `xunlei install` first",
+        program.display()
+    ))?;
+    read_interpreter(&mut file).context(format!("Failed to read ELF {}", program.display()))
+}
+
+fn read_interpreter<R: Read + Seek>(file: &mut R) -> std::io::Result<Option<PathBuf>> {
+    let mut ehdr = [0u8; 64];
+    let len = file.read(&mut ehdr)?;
+    if len < 52 || ehdr.starts_with(b"\x7fELF").not() {
+        return Ok(None);
+    }
+    // EI_CLASS and EI_DATA
+    let (is_64, little) = match (ehdr[4], ehdr[5]) {
+        (1 | 2, 1 | 2) => (ehdr[4] == 2, ehdr[5] == 1),
+        _ => return Ok(None),
+    };
+    let read = |buf: &[u8], at: usize, size: usize| {
+        let bytes = buf[at..at + size].iter().copied();
+        match little {
+            true => bytes.rev().fold(0u64, |n, b| n << 8 | b as u64),
+            false => bytes.fold(0u64, |n, b| n << 8 | b as u64),
         }
+    };
+    // e_phoff, e_phentsize and e_phnum
+    let (phoff, phentsize, phnum) = match is_64 {
+        true if len < 64 => return Ok(None),
+        true => (
+            read(&ehdr, 0x20, 8),
+            read(&ehdr, 0x36, 2),
+            read(&ehdr, 0x38, 2),
+        ),
+        false => (
+            read(&ehdr, 0x1c, 4),
+            read(&ehdr, 0x2a, 2),
+            read(&ehdr, 0x2c, 2),
+        ),
+    };
+    // Sizes of Elf64_Phdr and Elf32_Phdr
+    if phentsize != if is_64 { 56 } else { 32 } {
+        return Err(invalid_elf("unexpected program header size"));
     }
-    let sys_ld = Path::new(standard::SYS_LIB).join(LD);
-    if sys_ld.exists() {
-        std::fs::remove_file(&sys_ld)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let within =
+        |offset: u64, size: u64| offset.checked_add(size).is_some_and(|end| end <= file_len);
+    if within(phoff, phentsize * phnum).not() {
+        return Err(invalid_elf("program headers past the end of file"));
     }
-    let syno_ld = Path::new(standard::SYNOPKG_LIB).join(LD);
-    unsafe {
-        let source_path = CString::new(syno_ld.display().to_string())?;
-        let target_path = CString::new(sys_ld.display().to_string())?;
-        if libc::symlink(source_path.as_ptr(), target_path.as_ptr()) != 0 {
-            anyhow::bail!(std::io::Error::last_os_error());
+
+    let mut headers = vec![0u8; (phentsize * phnum) as usize];
+    file.seek(SeekFrom::Start(phoff))?;
+    file.read_exact(&mut headers)?;
+    for header in headers.chunks_exact(phentsize as usize) {
+        if read(header, 0, 4) != PT_INTERP as u64 {
+            continue;
+        }
+        // p_offset and p_filesz
+        let (offset, size) = match is_64 {
+            true => (read(header, 0x08, 8), read(header, 0x20, 8)),
+            false => (read(header, 0x04, 4), read(header, 0x10, 4)),
+        };
+        if size > libc::PATH_MAX as u64 || within(offset, size).not() {
+            return Err(invalid_elf("interpreter past the end of file or too long"));
+        }
+        let mut path = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut path)?;
+        let path = path.split(|b| *b == 0).next().unwrap_or_default();
+        return Ok(Some(PathBuf::from(
+            String::from_utf8_lossy(path).into_owned(),
+        )));
+    }
+    Ok(None)
+}
+
+fn invalid_elf(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// Whether the host loader at `interpreter` is a glibc one that finds every library of `program`
+fn host_glibc_runs(interpreter: &Path, program: &Path) -> bool {
+    // --list reports the libraries the loader can't find
+    is_glibc_loader(interpreter)
+        && loader_output(interpreter, "--verify", program)
+            .map(|verify| verify.status.success())
+            .unwrap_or(false)
+        && loader_output(interpreter, "--list", program)
+            .map(|list| {
+                list.status.success()
+                    && String::from_utf8_lossy(&list.stdout)
+                        .contains("not found")
+                        .not()
+            })
+            .unwrap_or(false)
+}
+
+// Whether `interpreter` is a host glibc loader, only those understand --verify. It accepts
+// itself as a static executable (2).
+fn is_glibc_loader(interpreter: &Path) -> bool {
+    // The bundled loader linked there by --replace-loader isn't the host's
+    if is_linked(interpreter) {
+        return false;
+    }
+    loader_output(interpreter, "--verify", interpreter)
+        .map(|verify| matches!(verify.status.code(), Some(0 | 2)))
+        .unwrap_or(false)
+}
+
+fn loader_output(interpreter: &Path, option: &str, program: &Path) -> std::io::Result<Output> {
+    Command::new(interpreter)
+        .arg(option)
+        .arg(program)
+        .env_remove("LD_LIBRARY_PATH")
+        .output()
+}
+
+// Whether `path` is a link into the bundled glibc
+fn is_linked(path: &Path) -> bool {
+    std::fs::read_link(path)
+        .map(|target| target.starts_with(standard::SYNOPKG_LIB))
+        .unwrap_or(false)
+}
+
+fn backup_path(sys_ld: &Path) -> PathBuf {
+    let mut backup = sys_ld.as_os_str().to_owned();
+    backup.push(BACKUP_SUFFIX);
+    PathBuf::from(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Little endian ELF with a PT_LOAD and, for dynamic programs, a PT_INTERP header
+    fn elf(is_64: bool, interpreter: Option<&str>) -> Vec<u8> {
+        let (ehsize, phentsize) = match is_64 {
+            true => (64, 56),
+            false => (52, 32),
+        };
+        let phnum = 1 + interpreter.is_some() as usize;
+        let mut elf = vec![0u8; ehsize + phentsize * phnum];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 1 + is_64 as u8;
+        elf[5] = 1;
+        let put = |elf: &mut Vec<u8>, at: usize, size: usize, value: u64| {
+            elf[at..at + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        };
+        match is_64 {
+            true => {
+                put(&mut elf, 0x20, 8, ehsize as u64);
+                put(&mut elf, 0x36, 2, phentsize as u64);
+                put(&mut elf, 0x38, 2, phnum as u64);
+            }
+            false => {
+                put(&mut elf, 0x1c, 4, ehsize as u64);
+                put(&mut elf, 0x2a, 2, phentsize as u64);
+                put(&mut elf, 0x2c, 2, phnum as u64);
+            }
+        }
+        put(&mut elf, ehsize, 4, 1);
+        if let Some(interpreter) = interpreter {
+            let header = ehsize + phentsize;
+            let (offset, size) = (elf.len() as u64, interpreter.len() as u64 + 1);
+            put(&mut elf, header, 4, PT_INTERP as u64);
+            match is_64 {
+                true => {
+                    put(&mut elf, header + 0x08, 8, offset);
+                    put(&mut elf, header + 0x20, 8, size);
+                }
+                false => {
+                    put(&mut elf, header + 0x04, 4, offset);
+                    put(&mut elf, header + 0x10, 4, size);
+                }
+            }
+            elf.extend_from_slice(interpreter.as_bytes());
+            elf.push(0);
+        }
+        elf
+    }
+
+    fn read(elf: &[u8]) -> String {
+        match read_interpreter(&mut Cursor::new(elf)) {
+            Ok(Some(path)) => path.display().to_string(),
+            Ok(None) => String::from("none"),
+            Err(_) => String::from("error"),
+        }
+    }
+
+    #[test]
+    fn test_read_interpreter() {
+        let dynamic = elf(true, Some("/lib64/ld-linux-x86-64.so.2"));
+        let mut big_phentsize = dynamic.clone();
+        big_phentsize[0x36] = 0xff;
+        let mut many_headers = dynamic.clone();
+        many_headers[0x38..0x3a].copy_from_slice(&[0xff, 0xff]);
+        let mut long_interpreter = dynamic.clone();
+        long_interpreter[64 + 56 + 0x20..64 + 56 + 0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut big_endian = dynamic.clone();
+        big_endian[5] = 2;
+
+        let cases = [
+            (dynamic.clone(), "/lib64/ld-linux-x86-64.so.2"),
+            (elf(false, Some("/lib/ld-linux.so.2")), "/lib/ld-linux.so.2"),
+            (elf(true, None), "none"),
+            (elf(false, None), "none"),
+            (b"#!/bin/sh\nexec true\n".to_vec(), "none"),
+            (Vec::new(), "none"),
+            (dynamic[..40].to_vec(), "none"),
+            (dynamic[..100].to_vec(), "error"),
+            (dynamic[..dynamic.len() - 4].to_vec(), "error"),
+            (big_phentsize, "error"),
+            (many_headers, "error"),
+            (long_interpreter, "error"),
+            // The interpreter is read as big endian garbage
+            (big_endian, "error"),
+        ];
+        for (elf, expected) in cases {
+            assert_eq!(read(&elf), expected, "{:02x?}", elf);
         }
     }
-    envs.insert(
-        String::from("LD_LIBRARY_PATH"),
-        standard::SYNOPKG_LIB.to_string(),
-    );
-    Ok(())
 }
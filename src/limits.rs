@@ -0,0 +1,339 @@
+use std::{
+    ffi::CString,
+    ops::Not,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+// Mount point of the unified cgroup v2 hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// Cgroup of the backend, next to the launcher's
+const BACKEND_CGROUP: &str = "xunlei-backend";
+// Leaf the launcher moves into, a cgroup holding processes can't hand controllers to children
+const LAUNCHER_CGROUP: &str = "xunlei-launcher";
+// Controllers the backend cgroup needs
+const CONTROLLERS: [&str; 2] = ["memory", "cpu"];
+// cpu.max period in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+// Memory, CPU and open file limits of the backend
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    // Bytes
+    pub memory: Option<u64>,
+    // Number of CPUs, fractions allowed
+    pub cpu: Option<f64>,
+    pub nofile: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Memory and CPU limits are only enforced by a cgroup
+    pub fn needs_cgroup(&self) -> bool {
+        self.memory.is_some() || self.cpu.is_some()
+    }
+
+    // memory.max of the backend cgroup
+    fn memory_max(&self) -> String {
+        match self.memory {
+            Some(memory) => memory.to_string(),
+            None => String::from("max"),
+        }
+    }
+
+    // cpu.max of the backend cgroup, the quota of the CPUs per period
+    fn cpu_max(&self) -> String {
+        match self.cpu {
+            Some(cpu) => format!(
+                "{} {}",
+                (cpu * CPU_PERIOD as f64).round() as u64,
+                CPU_PERIOD
+            ),
+            None => format!("max {}", CPU_PERIOD),
+        }
+    }
+
+    // Limit the spawned backend through `cgroup` when there is one, else with rlimits as far as
+    // they go. Must be applied before the process drops privileges.
+    pub fn apply(&self, cmd: &mut Command, cgroup: Option<&Cgroup>) -> anyhow::Result<()> {
+        let mut rlimits = Vec::new();
+        if let Some(nofile) = self.nofile {
+            rlimits.push((libc::RLIMIT_NOFILE, nofile));
+        }
+        match cgroup {
+            Some(cgroup) => cgroup.configure(self)?,
+            None => {
+                // Unlike RLIMIT_AS it ignores the address space that Go reserves up front
+                if let Some(memory) = self.memory {
+                    rlimits.push((libc::RLIMIT_DATA, memory));
+                }
+                if self.cpu.is_some() {
+                    log::warn!("[XunleiLauncher] The CPU limit requires cgroup v2, ignoring it");
+                }
+            }
+        }
+        let procs = cgroup
+            .map(|cgroup| CString::new(cgroup.path.join("cgroup.procs").as_os_str().as_bytes()))
+            .transpose()?;
+        if rlimits.is_empty() && procs.is_none() {
+            return Ok(());
+        }
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(procs) = &procs {
+                    // "0" stands for the writing process, nothing to format after fork
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                    let error = std::io::Error::last_os_error();
+                    libc::close(fd);
+                    if written != 1 {
+                        return Err(error);
+                    }
+                }
+                for (resource, limit) in &rlimits {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: *limit as libc::rlim_t,
+                        rlim_max: *limit as libc::rlim_t,
+                    };
+                    if libc::setrlimit(*resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Limits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits = Vec::new();
+        if let Some(memory) = self.memory {
+            limits.push(format!("memory {}M", memory / 1024 / 1024));
+        }
+        if let Some(cpu) = self.cpu {
+            limits.push(format!("cpu {}", cpu));
+        }
+        if let Some(nofile) = self.nofile {
+            limits.push(format!("nofile {}", nofile));
+        }
+        write!(f, "{}", limits.join(" "))
+    }
+}
+
+// Cgroup v2 subtree of the backend, every engine process it spawns stays inside
+pub struct Cgroup {
+    path: PathBuf,
+    // Leaf the launcher moved into, None in the root cgroup
+    leaf: Option<PathBuf>,
+    // Controllers we enabled in the parent's subtree_control
+    enabled: Vec<&'static str>,
+}
+
+impl Cgroup {
+    // Create the backend cgroup next to the launcher, None when cgroup v2 or its memory and cpu
+    // controllers aren't available to us
+    pub fn create() -> Option<Self> {
+        match Cgroup::try_create() {
+            Ok(cgroup) => {
+                log::info!("[XunleiLauncher] Backend cgroup: {}", cgroup.path.display());
+                Some(cgroup)
+            }
+            Err(e) => {
+                log::warn!(
+                    "[XunleiLauncher] No backend cgroup, memory and CPU limits fall back to rlimits: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn try_create() -> anyhow::Result<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        if root.join("cgroup.controllers").is_file().not() {
+            anyhow::bail!(format!("cgroup v2 isn't mounted at {}", CGROUP_ROOT));
+        }
+        let own = std::fs::read_to_string("/proc/self/cgroup")?;
+        let own = own
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .context("The launcher isn't in the cgroup v2 hierarchy")?;
+        let mut parent = root.join(own.trim_start_matches('/'));
+        if parent.ends_with(LAUNCHER_CGROUP) {
+            parent.pop();
+        }
+        // The root cgroup is exempt from the no internal processes rule, nobody else lives there
+        let leaf = (parent != root).then(|| parent.join(LAUNCHER_CGROUP));
+        if leaf.is_some() && delegated(&parent).not() {
+            anyhow::bail!(format!(
+                "{} isn't delegated to the launcher, run it as a systemd service with Delegate=yes",
+                parent.display()
+            ));
+        }
+
+        let available = std::fs::read_to_string(parent.join("cgroup.controllers"))?;
+        for controller in CONTROLLERS {
+            if available.split_whitespace().any(|c| c == controller).not() {
+                anyhow::bail!(format!(
+                    "the {} controller isn't delegated to {}",
+                    controller,
+                    parent.display()
+                ));
+            }
+        }
+        if let Some(leaf) = &leaf {
+            create_dir(leaf)?;
+            write(&leaf.join("cgroup.procs"), &std::process::id().to_string())?;
+        }
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let active = std::fs::read_to_string(&subtree_control)?;
+        let enabled = CONTROLLERS
+            .into_iter()
+            .filter(|controller| active.split_whitespace().any(|c| c == *controller).not())
+            .collect::<Vec<_>>();
+        let mut cgroup = Self {
+            path: parent.join(BACKEND_CGROUP),
+            leaf,
+            enabled: Vec::new(),
+        };
+        if enabled.is_empty().not() {
+            write(&subtree_control, &controllers(&enabled, '+'))?;
+            cgroup.enabled = enabled;
+        }
+        create_dir(&cgroup.path)?;
+        Ok(cgroup)
+    }
+
+    // Write the memory and CPU limits, unset ones are lifted
+    fn configure(&self, limits: &Limits) -> anyhow::Result<()> {
+        write(&self.path.join("memory.max"), &limits.memory_max())?;
+        write(&self.path.join("cpu.max"), &limits.cpu_max())
+    }
+
+    // Remove the backend cgroup once every engine process has exited, and move the launcher back
+    // out of its leaf
+    pub fn remove(&self) {
+        if let Err(e) = self.try_remove() {
+            log::warn!(
+                "[XunleiLauncher] Failed to remove the backend cgroup: {:?}",
+                e
+            );
+        }
+    }
+
+    fn try_remove(&self) -> anyhow::Result<()> {
+        remove_dir(&self.path)?;
+        let leaf = match &self.leaf {
+            Some(leaf) => leaf,
+            None => return Ok(()),
+        };
+        let parent = leaf.parent().context("The launcher cgroup has no parent")?;
+        // Processes may only join the parent again once it no longer hands out controllers
+        if self.enabled.is_empty().not() {
+            write(
+                &parent.join("cgroup.subtree_control"),
+                &controllers(&self.enabled, '-'),
+            )?;
+        }
+        write(
+            &parent.join("cgroup.procs"),
+            &std::process::id().to_string(),
+        )?;
+        remove_dir(leaf)
+    }
+}
+
+// Whether the cgroup was handed over to the launcher, by systemd's Delegate=yes or by chown
+fn delegated(path: &Path) -> bool {
+    let path_c = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path_c) => path_c,
+        Err(_) => return false,
+    };
+    // systemd marks delegated cgroups with an extended attribute
+    let marked = ["trusted.delegate", "user.delegate"].iter().any(|name| {
+        let name = CString::new(*name).unwrap();
+        let mut value = [0u8; 1];
+        let len = unsafe {
+            libc::getxattr(
+                path_c.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        len == 1 && value[0] == b'1'
+    });
+    // root owns every cgroup, ownership only tells delegation apart for other users
+    let euid = unsafe { libc::geteuid() };
+    marked
+        || (euid != 0
+            && std::fs::metadata(path)
+                .map(|metadata| metadata.uid() == euid)
+                .unwrap_or(false))
+}
+
+// `+memory +cpu` style list for cgroup.subtree_control
+fn controllers(controllers: &[&str], op: char) -> String {
+    controllers
+        .iter()
+        .map(|controller| format!("{}{}", op, controller))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn remove_dir(path: &Path) -> anyhow::Result<()> {
+    std::fs::remove_dir(path).context(format!("Failed to remove cgroup {}", path.display()))
+}
+
+fn create_dir(path: &Path) -> anyhow::Result<()> {
+    match std::fs::create_dir(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            Err(e).context(format!("Failed to create cgroup {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn write(path: &Path, value: &str) -> anyhow::Result<()> {
+    std::fs::write(path, value).context(format!(
+        "Failed to write `{}` to {}",
+        value,
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroup_values() {
+        let cases = [
+            (None, None, "max", "max 100000"),
+            (Some(536_870_912), Some(1.5), "536870912", "150000 100000"),
+            (Some(1), Some(0.29), "1", "29000 100000"),
+            (None, Some(0.01), "max", "1000 100000"),
+            (None, Some(16.0), "max", "1600000 100000"),
+        ];
+        for (memory, cpu, memory_max, cpu_max) in cases {
+            let limits = Limits {
+                memory,
+                cpu,
+                nofile: None,
+            };
+            assert_eq!(limits.memory_max(), memory_max, "{:?}", memory);
+            assert_eq!(limits.cpu_max(), cpu_max, "{:?}", cpu);
+        }
+    }
+}
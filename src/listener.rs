@@ -0,0 +1,432 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    ops::Not,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{acl::Cidr, proxy};
+
+// Poll interval of the relay accept loop while waiting for the stop signal
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Time allowed for a proxy to send the PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// UI listener given as `ADDR:PORT` or `unix:PATH`, followed by comma separated options:
+// `tls`, `no-tls`, `cert=FILE`, `key=FILE`, `allow=CIDR`, `deny=CIDR` and `proxy-protocol`
+#[derive(Clone, PartialEq)]
+pub struct ListenSpec {
+    spec: String,
+    pub addr: ListenAddr,
+    // Unset follows the global TLS settings
+    pub tls: Option<bool>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    // Connections open with a PROXY protocol v1/v2 header
+    pub proxy_protocol: bool,
+}
+
+impl ListenSpec {
+    pub fn new(addr: ListenAddr) -> Self {
+        Self {
+            spec: addr.to_string(),
+            addr,
+            tls: None,
+            cert: None,
+            key: None,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            proxy_protocol: false,
+        }
+    }
+
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.trim().split(',');
+        let addr = parts.next().unwrap_or_default().trim();
+        let addr = match addr.strip_prefix("unix:") {
+            Some(path) if path.is_empty().not() => ListenAddr::Unix(PathBuf::from(path)),
+            Some(_) => anyhow::bail!(format!("`{}` has an empty socket path", s)),
+            None => ListenAddr::Tcp(addr.parse().map_err(|_| {
+                anyhow::anyhow!(format!("`{}` isn't a ADDR:PORT or unix:PATH listener", s))
+            })?),
+        };
+        let mut spec = Self {
+            spec: s.trim().to_string(),
+            ..Self::new(addr)
+        };
+        for option in parts.map(str::trim).filter(|o| o.is_empty().not()) {
+            match option.split_once('=') {
+                None if option == "tls" => spec.tls = Some(true),
+                None if option == "no-tls" => spec.tls = Some(false),
+                None if option == "proxy-protocol" => spec.proxy_protocol = true,
+                Some(("cert", path)) => spec.cert = Some(PathBuf::from(path)),
+                Some(("key", path)) => spec.key = Some(PathBuf::from(path)),
+                Some(("allow", cidr)) => spec.allow.push(Cidr::parse(cidr)?),
+                Some(("deny", cidr)) => spec.deny.push(Cidr::parse(cidr)?),
+                _ => anyhow::bail!(format!("`{}` has an unknown option `{}`", s, option)),
+            }
+        }
+        if spec.cert.is_some() != spec.key.is_some() {
+            anyhow::bail!(format!("`{}` needs both cert= and key=", s));
+        }
+        Ok(spec)
+    }
+}
+
+impl Display for ListenSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+// Local addresses of the loopback connections opened by a relay, with the address of the client
+// when it is known: the TCP peer or the source announced in the PROXY protocol header
+pub type RelayPorts = Arc<Mutex<HashMap<SocketAddr, Option<SocketAddr>>>>;
+
+// Socket accepting the connections of a relay
+pub enum RelaySocket {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl RelaySocket {
+    pub fn bind(addr: &ListenAddr) -> anyhow::Result<Self> {
+        let socket = match addr {
+            ListenAddr::Tcp(addr) => RelaySocket::Tcp(
+                TcpListener::bind(addr)
+                    .or_else(|e| match addr.ip() {
+                        // Fall back to IPv4 on hosts without IPv6
+                        IpAddr::V6(ip) if ip.is_unspecified() => {
+                            log::warn!(
+                                "[XunleiLauncher] Failed to bind {}: {}, falling back to 0.0.0.0",
+                                addr,
+                                e
+                            );
+                            TcpListener::bind((Ipv4Addr::UNSPECIFIED, addr.port()))
+                        }
+                        _ => Err(e),
+                    })
+                    .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", addr, e))?,
+            ),
+            ListenAddr::Unix(path) => {
+                // A socket left behind by an unclean shutdown would make bind fail
+                if UnixStream::connect(path).is_err() && path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", path.display(), e))?;
+                RelaySocket::Unix(listener, path.clone())
+            }
+        };
+        match &socket {
+            RelaySocket::Tcp(listener) => listener.set_nonblocking(true)?,
+            RelaySocket::Unix(listener, _) => listener.set_nonblocking(true)?,
+        }
+        Ok(socket)
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<ListenAddr> {
+        match self {
+            RelaySocket::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            RelaySocket::Unix(_, path) => Ok(ListenAddr::Unix(path.clone())),
+        }
+    }
+
+    fn accept(&self) -> std::io::Result<(Connection, Option<SocketAddr>)> {
+        match self {
+            RelaySocket::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, peer)| (Connection::Tcp(stream), Some(peer))),
+            RelaySocket::Unix(listener, _) => listener
+                .accept()
+                .map(|(stream, _)| (Connection::Unix(stream), None)),
+        }
+    }
+}
+
+// Accept connections on `socket` and forward them to the loopback UI server at `target`.
+// rouille only listens on TCP without PROXY protocol support, the server rejects peers that are
+// not the local end of a connection in `ports`. With `proxy_protocol` set, TCP connections are only accepted from the
+// trusted proxies.
+pub fn relay(
+    socket: RelaySocket,
+    target: SocketAddr,
+    ports: RelayPorts,
+    proxy_protocol: Option<Arc<Vec<Cidr>>>,
+) -> anyhow::Result<(JoinHandle<()>, mpsc::Sender<()>)> {
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let handle = std::thread::Builder::new()
+        .name("ui-relay".to_string())
+        .spawn(move || {
+            loop {
+                match socket.accept() {
+                    Ok((stream, peer)) => {
+                        let relayed = Relayed {
+                            stream,
+                            peer,
+                            target,
+                            ports: ports.clone(),
+                            proxy_protocol: proxy_protocol.clone(),
+                        };
+                        if let Err(e) = std::thread::Builder::new()
+                            .name("ui-relay-conn".to_string())
+                            .spawn(move || relayed.forward())
+                        {
+                            log::error!("[XunleiLauncher] Relay error: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        if stop_rx.recv_timeout(ACCEPT_POLL_INTERVAL).is_ok() {
+                            break;
+                        }
+                    }
+                    Err(e) => log::error!("[XunleiLauncher] Relay accept error: {}", e),
+                }
+            }
+            if let RelaySocket::Unix(_, path) = &socket {
+                let _ = std::fs::remove_file(path);
+            }
+        })?;
+    Ok((handle, stop_tx))
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(how),
+            Connection::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Connection::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// A connection accepted by a relay
+struct Relayed {
+    stream: Connection,
+    peer: Option<SocketAddr>,
+    target: SocketAddr,
+    ports: RelayPorts,
+    proxy_protocol: Option<Arc<Vec<Cidr>>>,
+}
+
+impl Relayed {
+    fn forward(self) {
+        let peer = self
+            .peer
+            .map(|peer| peer.to_string())
+            .unwrap_or_else(|| String::from("unix socket"));
+        if let Err(e) = self.try_forward() {
+            log::warn!("[XunleiLauncher] Relay connection from {}: {}", peer, e);
+        }
+    }
+
+    fn try_forward(self) -> std::io::Result<()> {
+        let mut client = self.stream;
+        client.set_nonblocking(false)?;
+        let mut remote = self.peer;
+        if let Some(trusted) = &self.proxy_protocol {
+            if let Some(peer) = self.peer {
+                if trusted.iter().any(|cidr| cidr.contains(peer.ip())).not() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::PermissionDenied,
+                        "PROXY protocol header from an untrusted peer",
+                    ));
+                }
+            }
+            client.set_read_timeout(Some(PROXY_HEADER_TIMEOUT))?;
+            if let Some(source) = proxy::read_header(&mut client)? {
+                remote = Some(source);
+            }
+            client.set_read_timeout(None)?;
+        }
+
+        let upstream = TcpStream::connect(self.target)?;
+        let local = upstream.local_addr()?;
+        let ports = self.ports;
+        ports.lock().unwrap().insert(local, remote);
+
+        let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+        std::thread::Builder::new()
+            .name("ui-relay-conn".to_string())
+            .spawn(move || {
+                let _ = std::io::copy(&mut client_read, &mut upstream_write);
+                let _ = upstream_write.shutdown(Shutdown::Write);
+            })?;
+        let (mut upstream_read, mut client_write) = (upstream, client);
+        let _ = std::io::copy(&mut upstream_read, &mut client_write);
+        let _ = client_write.shutdown(Shutdown::Write);
+        ports.lock().unwrap().remove(&local);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Address and options of a parsed listener in a comparable form
+    fn describe(spec: &ListenSpec) -> String {
+        let cidrs = |cidrs: &[Cidr]| {
+            cidrs
+                .iter()
+                .map(Cidr::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "{} tls={:?} cert={:?} key={:?} allow=[{}] deny=[{}] proxy={}",
+            spec.addr,
+            spec.tls,
+            spec.cert,
+            spec.key,
+            cidrs(&spec.allow),
+            cidrs(&spec.deny),
+            spec.proxy_protocol
+        )
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            (
+                "0.0.0.0:5055",
+                Some("0.0.0.0:5055 tls=None cert=None key=None allow=[] deny=[] proxy=false"),
+            ),
+            (
+                " 127.0.0.1:5055 ",
+                Some("127.0.0.1:5055 tls=None cert=None key=None allow=[] deny=[] proxy=false"),
+            ),
+            (
+                "[::]:5055,tls",
+                Some("[::]:5055 tls=Some(true) cert=None key=None allow=[] deny=[] proxy=false"),
+            ),
+            (
+                "[fe80::1]:443,no-tls",
+                Some("[fe80::1]:443 tls=Some(false) cert=None key=None allow=[] deny=[] proxy=false"),
+            ),
+            (
+                "0.0.0.0:443, tls, cert=/etc/ui.crt, key=/etc/ui.key",
+                Some("0.0.0.0:443 tls=Some(true) cert=Some(\"/etc/ui.crt\") key=Some(\"/etc/ui.key\") allow=[] deny=[] proxy=false"),
+            ),
+            (
+                "0.0.0.0:5055,allow=192.168.1.0/24,allow=fd00::/8,deny=192.168.1.9",
+                Some("0.0.0.0:5055 tls=None cert=None key=None allow=[192.168.1.0/24 fd00::/8] deny=[192.168.1.9/32] proxy=false"),
+            ),
+            (
+                "0.0.0.0:5055,proxy-protocol,",
+                Some("0.0.0.0:5055 tls=None cert=None key=None allow=[] deny=[] proxy=true"),
+            ),
+            (
+                "unix:/run/xunlei.sock",
+                Some("unix:/run/xunlei.sock tls=None cert=None key=None allow=[] deny=[] proxy=false"),
+            ),
+            (
+                "unix:/run/xunlei,ui.sock",
+                None,
+            ),
+            (
+                "unix:relative.sock,proxy-protocol",
+                Some("unix:relative.sock tls=None cert=None key=None allow=[] deny=[] proxy=true"),
+            ),
+            ("unix:", None),
+            ("unix:,tls", None),
+            ("::1:5055", None),
+            ("[::1]", None),
+            ("localhost:5055", None),
+            ("0.0.0.0", None),
+            ("", None),
+            ("0.0.0.0:5055,tls=yes", None),
+            ("0.0.0.0:5055,cert=/etc/ui.crt", None),
+            ("0.0.0.0:5055,key=/etc/ui.key", None),
+            ("0.0.0.0:5055,allow=10.0.0.0/33", None),
+            ("0.0.0.0:5055,deny=example.com", None),
+            ("0.0.0.0:5055,gzip", None),
+        ];
+        for (input, expected) in cases {
+            let parsed = ListenSpec::parse(input).ok().map(|spec| describe(&spec));
+            assert_eq!(parsed.as_deref(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn display_keeps_the_spec() {
+        let spec = ListenSpec::parse(" 0.0.0.0:5055,tls ").unwrap();
+        assert_eq!(spec.to_string(), "0.0.0.0:5055,tls");
+        let spec = ListenSpec::new(ListenAddr::Unix(PathBuf::from("/run/xunlei.sock")));
+        assert_eq!(spec.to_string(), "unix:/run/xunlei.sock");
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Context;
+use std::{
+    io::Write,
+    ops::Not,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct LogRotation {
+    // Rotate when the file grows beyond this many bytes, 0 disables
+    pub max_size: u64,
+    // Rotate when the file was last rotated this long ago, zero disables
+    pub max_age: Duration,
+    // Number of gzipped archives to keep
+    pub keep: usize,
+}
+
+struct LogFile {
+    path: PathBuf,
+    rotated_at: Instant,
+}
+
+// Rotates log files in place: the content is gzipped into `<file>.1.gz` and the file is
+// truncated, so processes that keep the file open continue to write to it. Only writers in
+// append mode start over at the beginning, the others leave a hole up to their old offset.
+pub struct LogRotator {
+    rotation: LogRotation,
+    files: Vec<LogFile>,
+}
+
+impl LogRotator {
+    pub fn new(rotation: LogRotation) -> Self {
+        Self {
+            rotation,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        if self.files.iter().any(|file| file.path == path) {
+            return;
+        }
+        self.files.push(LogFile {
+            path,
+            rotated_at: Instant::now(),
+        });
+    }
+
+    pub fn set_rotation(&mut self, rotation: LogRotation) {
+        self.rotation = rotation;
+    }
+
+    // Rotate every watched file that exceeds the size or age limit
+    pub fn check(&mut self) {
+        let rotation = self.rotation;
+        for file in self.files.iter_mut() {
+            let size = match std::fs::metadata(&file.path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            let oversize = rotation.max_size > 0 && size >= rotation.max_size;
+            let expired = rotation.max_age.is_zero().not()
+                && file.rotated_at.elapsed() >= rotation.max_age
+                && size > 0;
+            if oversize || expired {
+                match rotate(&file.path, rotation.keep) {
+                    Ok(()) => log::info!("[LogRotator] Rotated {}", file.path.display()),
+                    Err(e) => log::error!(
+                        "[LogRotator] Failed to rotate {}: {:?}",
+                        file.path.display(),
+                        e
+                    ),
+                }
+                file.rotated_at = Instant::now();
+            }
+        }
+    }
+}
+
+fn archive(path: &Path, index: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}.gz", path.display(), index))
+}
+
+fn rotate(path: &Path, keep: usize) -> anyhow::Result<()> {
+    if keep > 0 {
+        let oldest = archive(path, keep);
+        if oldest.exists() {
+            std::fs::remove_file(&oldest)?;
+        }
+        for index in (1..keep).rev() {
+            let source = archive(path, index);
+            if source.exists() {
+                std::fs::rename(&source, archive(path, index + 1))?;
+            }
+        }
+
+        let mut source = std::fs::File::open(path)?;
+        let target = archive(path, 1);
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&target)
+                .context(format!("Failed to create {}", target.display()))?,
+            flate2::Compression::default(),
+        );
+        std::io::copy(&mut source, &mut encoder)?;
+        encoder.finish()?.flush()?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(0)
+        .context(format!("Failed to truncate {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const ROTATION: LogRotation = LogRotation {
+        max_size: 10,
+        max_age: Duration::from_secs(3600),
+        keep: 2,
+    };
+
+    fn gunzip(path: &Path) -> String {
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn thresholds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("xunlei.log");
+        let mut rotator = LogRotator::new(ROTATION);
+        rotator.watch(&path);
+        rotator.watch(&path);
+        assert_eq!(rotator.files.len(), 1);
+
+        // Missing and small files are left alone
+        rotator.check();
+        std::fs::write(&path, "small").unwrap();
+        rotator.check();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "small");
+        assert!(archive(&path, 1).exists().not());
+
+        std::fs::write(&path, "0123456789").unwrap();
+        rotator.check();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(gunzip(&archive(&path, 1)), "0123456789");
+
+        // Expired files are rotated unless they are empty
+        rotator.files[0].rotated_at = Instant::now() - ROTATION.max_age;
+        rotator.check();
+        assert_eq!(gunzip(&archive(&path, 1)), "0123456789");
+        assert!(archive(&path, 2).exists().not());
+        std::fs::write(&path, "old").unwrap();
+        rotator.files[0].rotated_at = Instant::now() - ROTATION.max_age;
+        rotator.check();
+        assert_eq!(gunzip(&archive(&path, 1)), "old");
+        assert_eq!(gunzip(&archive(&path, 2)), "0123456789");
+
+        // Disabled limits never rotate
+        rotator.set_rotation(LogRotation {
+            max_size: 0,
+            max_age: Duration::ZERO,
+            keep: 2,
+        });
+        std::fs::write(&path, "0123456789").unwrap();
+        rotator.files[0].rotated_at = Instant::now() - ROTATION.max_age;
+        rotator.check();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("xunlei.log");
+        for content in ["first", "second", "third", "fourth"] {
+            std::fs::write(&path, content).unwrap();
+            rotate(&path, 3).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(gunzip(&archive(&path, 1)), "fourth");
+        assert_eq!(gunzip(&archive(&path, 2)), "third");
+        assert_eq!(gunzip(&archive(&path, 3)), "second");
+        assert!(archive(&path, 4).exists().not());
+
+        // Without archives the file is only truncated
+        std::fs::write(&path, "fifth").unwrap();
+        rotate(&path, 0).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(gunzip(&archive(&path, 1)), "fourth");
+    }
+
+    #[test]
+    fn open_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("xunlei.log");
+        let mut appending = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+        appending.write_all(b"before\n").unwrap();
+        rotate(&path, 1).unwrap();
+        appending.write_all(b"after\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+
+        // A writer without O_APPEND keeps its offset and leaves a hole of zeros
+        let mut positioned = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        positioned.write_all(b"after\nmore\n").unwrap();
+        rotate(&path, 1).unwrap();
+        positioned.write_all(b"late\n").unwrap();
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(content, b"\0\0\0\0\0\0\0\0\0\0\0late\n");
+    }
+}
@@ -1,14 +1,36 @@
+pub mod accesslog;
+pub mod acl;
+#[cfg(feature = "launch")]
+pub mod auth;
+pub mod basepath;
+#[cfg(feature = "launch")]
+pub mod cgi;
+#[cfg(feature = "launch")]
+pub mod daemon;
 #[cfg(feature = "launch")]
 pub mod launch;
-#[cfg(all(target_os = "linux", target_env = "musl"))]
-#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub mod libc_asset;
+#[cfg(feature = "launch")]
+pub mod limits;
+pub mod listener;
+#[cfg(feature = "launch")]
+pub mod logrotate;
+#[cfg(feature = "launch")]
+pub mod metrics;
+#[cfg(feature = "launch")]
+pub mod namespace;
+pub mod proxy;
+#[cfg(feature = "launch")]
+pub mod runas;
 pub mod standard;
 #[cfg(feature = "systemd")]
 pub mod systemd;
+#[cfg(feature = "launch")]
+pub mod tls;
 #[cfg(feature = "systemd")]
 pub mod xunlei_asset;
 
+use anyhow::Context;
 use clap::{Args, Parser, Subcommand};
 use std::io::Write;
 use std::path::PathBuf;
@@ -38,14 +60,52 @@ pub enum Commands {
     Uninstall,
     #[cfg(feature = "launch")]
     /// Launch xunlei
-    Launch(Config),
+    Launch {
+        #[clap(flatten)]
+        config: Config,
+        #[clap(flatten)]
+        daemon: Daemon,
+    },
+    #[cfg(feature = "launch")]
+    /// Stop the xunlei daemon
+    Stop {
+        /// Daemon pid file
+        #[clap(long, default_value = standard::LAUNCH_PID_FILE)]
+        pidfile: PathBuf,
+        /// Seconds to wait for the daemon to exit
+        #[clap(long, default_value = "30")]
+        timeout: u64,
+    },
+    #[cfg(feature = "launch")]
+    /// Set the web UI password
+    Passwd {
+        /// Xunlei config directory
+        #[clap(short, long, default_value = standard::SYNOPKG_PKGBASE)]
+        config_path: PathBuf,
+        /// Remove the password and disable authentication
+        #[clap(long)]
+        remove: bool,
+    },
 }
 
 #[derive(Args)]
+pub struct Daemon {
+    /// Run xunlei in the background
+    #[clap(long)]
+    daemon: bool,
+    /// Daemon pid file
+    #[clap(long, default_value = standard::LAUNCH_PID_FILE)]
+    pidfile: PathBuf,
+    /// Daemon log file
+    #[clap(long, default_value = standard::DAEMON_LOG_FILE)]
+    log_file: PathBuf,
+}
+
+#[derive(Args, Clone)]
 pub struct Config {
-    /// Xunlei Listen host
-    #[clap(short, long, default_value = "0.0.0.0", value_parser = parser_host)]
-    host: std::net::IpAddr,
+    /// Xunlei Listen host, dual-stack [::] falling back to 0.0.0.0 when unset
+    #[clap(short, long, value_parser = parser_host)]
+    host: Option<std::net::IpAddr>,
     /// Xunlei Listen port
     #[clap(short, long, default_value = "5055", value_parser = parser_port_in_range)]
     port: u16,
@@ -55,6 +115,303 @@ pub struct Config {
     /// Xunlei download directory
     #[clap(short, long, default_value = standard::TMP_DOWNLOAD_PATH)]
     download_path: PathBuf,
+    /// Maximum number of times a crashed backend is restarted
+    #[clap(long, default_value = "10")]
+    max_restarts: u32,
+    /// Initial backend restart delay in seconds, doubled after each crash
+    #[clap(long, default_value = "1")]
+    restart_delay: u64,
+    /// Seconds to wait for processes to exit on shutdown before sending SIGKILL
+    #[clap(long, default_value = "10")]
+    stop_timeout: u64,
+    /// Rotate log files larger than this many megabytes, 0 disables
+    #[clap(long, default_value = "10")]
+    log_max_size: u64,
+    /// Rotate log files older than this many days, 0 disables
+    #[clap(long, default_value = "7")]
+    log_max_age: u64,
+    /// Number of rotated log files to keep
+    #[clap(long, default_value = "5")]
+    log_keep: usize,
+    /// Maximum number of UI CGI processes running at the same time
+    #[clap(long, default_value = "8", value_parser = parser_concurrency)]
+    cgi_max_concurrency: usize,
+    /// Maximum number of UI requests waiting for a CGI process, beyond which 503 is returned
+    #[clap(long, default_value = "32")]
+    cgi_max_queue: usize,
+    /// Seconds after which a UI CGI process is killed, 0 disables
+    #[clap(long, default_value = "120")]
+    cgi_timeout: u64,
+    /// Memory limit of the backend such as 512M or 2G, enforced by cgroup v2 when available
+    #[clap(long, value_parser = parser_memory)]
+    memory_limit: Option<u64>,
+    /// Number of CPUs the backend may use such as 1.5, requires cgroup v2
+    #[clap(long, value_parser = parser_cpu)]
+    cpu_limit: Option<f64>,
+    /// Maximum number of files the backend may open
+    #[clap(long)]
+    nofile_limit: Option<u64>,
+    /// Extra environment variable passed to Xunlei, as KEY=VALUE
+    #[clap(long = "env", value_parser = parser_env)]
+    envs: Vec<(String, String)>,
+    /// Serve the UI over HTTPS, with a self-signed certificate unless --tls-cert is given
+    #[clap(long)]
+    tls: bool,
+    /// PEM certificate chain of the HTTPS UI, implies --tls
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the HTTPS UI, implies --tls
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Plain HTTP port that redirects to the HTTPS UI
+    #[clap(long, value_parser = parser_port_in_range)]
+    http_redirect_port: Option<u16>,
+    /// UI listener as ADDR:PORT or unix:PATH with options such as `,tls,allow=CIDR`, repeatable,
+    /// replaces --host/--port
+    #[clap(long, value_parser = parser_listen)]
+    listen: Vec<listener::ListenSpec>,
+    /// Client address or CIDR allowed to reach the UI, repeatable, everyone when unset
+    #[clap(long, value_parser = parser_cidr)]
+    allow: Vec<acl::Cidr>,
+    /// Client address or CIDR denied access to the UI, repeatable, takes precedence over --allow
+    #[clap(long, value_parser = parser_cidr)]
+    deny: Vec<acl::Cidr>,
+    /// Run the backend and CGI in a private mount namespace holding the fake Synology files,
+    /// instead of linking them into the host /etc and /usr
+    #[clap(long)]
+    isolate: bool,
+    /// Link the bundled glibc loader where the Xunlei programs expect theirs (e.g. /lib) when it
+    /// is used, as older releases did. Never replaces the loader of a host glibc. Anything else
+    /// there is backed up and restored on exit, the next start and uninstall
+    #[clap(long)]
+    replace_loader: bool,
+    /// Libc the Xunlei programs run with: auto detects whether the host glibc can run them,
+    /// system or bundled force one
+    #[clap(long, default_value = "auto", value_parser = parser_libc)]
+    libc: libc_asset::LibcMode,
+    /// User name or uid the backend and CGI run as, the launcher itself needs root
+    #[clap(long, env = "PUID")]
+    user: Option<String>,
+    /// Group name or gid the backend and CGI run as, defaults to the primary group of --user
+    #[clap(long, env = "PGID")]
+    group: Option<String>,
+    /// Octal umask of the backend and CGI, e.g. 022
+    #[clap(long, env = "UMASK", value_parser = parser_umask)]
+    umask: Option<u32>,
+    /// Reverse proxy address or CIDR whose X-Forwarded-For, Forwarded and PROXY protocol headers
+    /// are trusted, repeatable
+    #[clap(long, value_parser = parser_cidr)]
+    trusted_proxy: Vec<acl::Cidr>,
+    /// Hours a web UI login session stays valid
+    #[clap(long, default_value = "24")]
+    session_ttl: u64,
+    /// Path prefix of the UI behind a reverse proxy, e.g. /xunlei
+    #[clap(long, default_value = "/", value_parser = parser_base_path)]
+    base_path: basepath::BasePath,
+    /// Serve Prometheus metrics on /metrics
+    #[clap(long)]
+    metrics: bool,
+    /// File holding the bearer token required to scrape /metrics, implies --metrics. It must not
+    /// be accessible to group or others
+    #[clap(long)]
+    metrics_token_file: Option<PathBuf>,
+    // Only set by the configuration file, so that the secret never shows up in a command line
+    #[clap(skip)]
+    metrics_token: Option<String>,
+    /// UI access log file, disabled when unset
+    #[clap(long)]
+    access_log: Option<PathBuf>,
+    /// UI access log format: combined or json
+    #[clap(long, default_value = "combined", value_parser = parser_access_log_format)]
+    access_log_format: accesslog::AccessLogFormat,
+    /// Launcher configuration file, re-read on SIGHUP
+    #[clap(long)]
+    config_file: Option<PathBuf>,
+}
+
+impl Config {
+    // Apply the `key = value` lines of the configuration file on top of the command line
+    pub(crate) fn load(&self) -> anyhow::Result<Config> {
+        let mut config = self.clone();
+        let path = match &self.config_file {
+            Some(path) => path,
+            None => return Ok(config),
+        };
+        let content = std::fs::read_to_string(path)
+            .context(format!("Failed to read config file: {}", path.display()))?;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').context(format!(
+                "{}:{}: expected `key = value`",
+                path.display(),
+                index + 1
+            ))?;
+            config
+                .set(key.trim(), value.trim().trim_matches('"'))
+                .context(format!("{}:{}", path.display(), index + 1))?;
+        }
+        Ok(config)
+    }
+
+    // Resolve the relative paths of the command line against the working directory
+    #[cfg(feature = "launch")]
+    fn absolutize(&mut self) -> anyhow::Result<()> {
+        for path in [&mut self.config_path, &mut self.download_path] {
+            *path = std::path::absolute(&*path)?;
+        }
+        for path in [
+            &mut self.tls_cert,
+            &mut self.tls_key,
+            &mut self.metrics_token_file,
+            &mut self.access_log,
+            &mut self.config_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = std::path::absolute(&*path)?;
+        }
+        Ok(())
+    }
+
+    // Command line arguments that launch xunlei with this configuration
+    pub(crate) fn launch_args(&self) -> Vec<String> {
+        let mut args = vec![format!("--port {}", self.port)];
+        if let Some(host) = self.host {
+            args.push(format!("--host {}", host));
+        }
+        args.extend([
+            format!("--config-path {}", self.config_path.display()),
+            format!("--download-path {}", self.download_path.display()),
+            format!("--max-restarts {}", self.max_restarts),
+            format!("--restart-delay {}", self.restart_delay),
+            format!("--stop-timeout {}", self.stop_timeout),
+            format!("--log-max-size {}", self.log_max_size),
+            format!("--log-max-age {}", self.log_max_age),
+            format!("--log-keep {}", self.log_keep),
+            format!("--cgi-max-concurrency {}", self.cgi_max_concurrency),
+            format!("--cgi-max-queue {}", self.cgi_max_queue),
+            format!("--cgi-timeout {}", self.cgi_timeout),
+            format!("--session-ttl {}", self.session_ttl),
+            format!("--base-path {}", self.base_path),
+            format!("--access-log-format {}", self.access_log_format),
+        ]);
+        if let Some(access_log) = &self.access_log {
+            args.push(format!("--access-log {}", access_log.display()));
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            args.push(format!("--memory-limit {}", memory_limit));
+        }
+        if let Some(cpu_limit) = self.cpu_limit {
+            args.push(format!("--cpu-limit {}", cpu_limit));
+        }
+        if let Some(nofile_limit) = self.nofile_limit {
+            args.push(format!("--nofile-limit {}", nofile_limit));
+        }
+        if self.metrics {
+            args.push(String::from("--metrics"));
+        }
+        if let Some(token_file) = &self.metrics_token_file {
+            args.push(format!("--metrics-token-file {}", token_file.display()));
+        }
+        for listen in &self.listen {
+            args.push(format!("--listen \"{}\"", listen));
+        }
+        for (key, value) in &self.envs {
+            args.push(format!("--env \"{}={}\"", key, value));
+        }
+        for cidr in &self.allow {
+            args.push(format!("--allow {}", cidr));
+        }
+        for cidr in &self.deny {
+            args.push(format!("--deny {}", cidr));
+        }
+        if self.isolate {
+            args.push(String::from("--isolate"));
+        }
+        if self.replace_loader {
+            args.push(String::from("--replace-loader"));
+        }
+        if self.libc != libc_asset::LibcMode::Auto {
+            args.push(format!("--libc {}", self.libc));
+        }
+        if let Some(user) = &self.user {
+            args.push(format!("--user {}", user));
+        }
+        if let Some(group) = &self.group {
+            args.push(format!("--group {}", group));
+        }
+        if let Some(umask) = self.umask {
+            args.push(format!("--umask {:03o}", umask));
+        }
+        for cidr in &self.trusted_proxy {
+            args.push(format!("--trusted-proxy {}", cidr));
+        }
+        if self.tls {
+            args.push(String::from("--tls"));
+        }
+        if let Some(tls_cert) = &self.tls_cert {
+            args.push(format!("--tls-cert {}", tls_cert.display()));
+        }
+        if let Some(tls_key) = &self.tls_key {
+            args.push(format!("--tls-key {}", tls_key.display()));
+        }
+        if let Some(port) = self.http_redirect_port {
+            args.push(format!("--http-redirect-port {}", port));
+        }
+        if let Some(config_file) = &self.config_file {
+            args.push(format!("--config-file {}", config_file.display()));
+        }
+        args
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key.replace('-', "_").as_str() {
+            "host" => self.host = Some(parser_host(value)?),
+            "port" => self.port = parser_port_in_range(value)?,
+            "config_path" => self.config_path = PathBuf::from(value),
+            "download_path" => self.download_path = PathBuf::from(value),
+            "max_restarts" => self.max_restarts = value.parse()?,
+            "restart_delay" => self.restart_delay = value.parse()?,
+            "stop_timeout" => self.stop_timeout = value.parse()?,
+            "log_max_size" => self.log_max_size = value.parse()?,
+            "log_max_age" => self.log_max_age = value.parse()?,
+            "log_keep" => self.log_keep = value.parse()?,
+            "cgi_max_concurrency" => self.cgi_max_concurrency = parser_concurrency(value)?,
+            "cgi_max_queue" => self.cgi_max_queue = value.parse()?,
+            "cgi_timeout" => self.cgi_timeout = value.parse()?,
+            "memory_limit" => self.memory_limit = Some(parser_memory(value)?),
+            "cpu_limit" => self.cpu_limit = Some(parser_cpu(value)?),
+            "nofile_limit" => self.nofile_limit = Some(value.parse()?),
+            "env" => self.envs.push(parser_env(value)?),
+            "tls" => self.tls = parser_bool(value)?,
+            "tls_cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" => self.tls_key = Some(PathBuf::from(value)),
+            "listen" => self.listen.push(parser_listen(value)?),
+            "allow" => self.allow.push(parser_cidr(value)?),
+            "deny" => self.deny.push(parser_cidr(value)?),
+            "isolate" => self.isolate = parser_bool(value)?,
+            "replace_loader" => self.replace_loader = parser_bool(value)?,
+            "libc" => self.libc = parser_libc(value)?,
+            "user" => self.user = Some(value.to_string()),
+            "group" => self.group = Some(value.to_string()),
+            "umask" => self.umask = Some(parser_umask(value)?),
+            "trusted_proxy" => self.trusted_proxy.push(parser_cidr(value)?),
+            "metrics" => self.metrics = parser_bool(value)?,
+            "metrics_token" => self.metrics_token = Some(value.to_string()),
+            "metrics_token_file" => self.metrics_token_file = Some(PathBuf::from(value)),
+            "access_log" => self.access_log = Some(PathBuf::from(value)),
+            "base_path" => self.base_path = parser_base_path(value)?,
+            "access_log_format" => self.access_log_format = parser_access_log_format(value)?,
+            "session_ttl" => self.session_ttl = value.parse()?,
+            "http_redirect_port" => self.http_redirect_port = Some(parser_port_in_range(value)?),
+            _ => anyhow::bail!("unknown config key `{}`", key),
+        }
+        Ok(())
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -63,16 +420,52 @@ fn main() -> anyhow::Result<()> {
     match opt.commands {
         #[cfg(feature = "systemd")]
         Commands::Install(config) => {
-            systemd::XunleiInstall::from(config).launch()?;
+            systemd::XunleiInstall::try_from(&config)?.launch()?;
         }
         #[cfg(feature = "systemd")]
         Commands::Uninstall => {
             systemd::XunleiUninstall {}.launch()?;
         }
         #[cfg(feature = "launch")]
-        Commands::Launch(config) => {
-            launch::XunleiLauncher::from(config).launch()?;
+        Commands::Launch {
+            mut config,
+            mut daemon,
+        } => {
+            // The daemon changes its working directory to /
+            if daemon.daemon {
+                config.absolutize()?;
+                daemon.pidfile = std::path::absolute(&daemon.pidfile)?;
+                daemon.log_file = std::path::absolute(&daemon.log_file)?;
+            }
+            let mut launcher = launch::XunleiLauncher::try_from(config)?;
+            let _pidfile = match daemon.daemon {
+                true => {
+                    let pidfile = daemon::daemonize(&daemon.pidfile, &daemon.log_file)?;
+                    launcher = launcher.with_log_file(daemon.log_file);
+                    Some(pidfile)
+                }
+                false => None,
+            };
+            launcher.launch()?;
         }
+        #[cfg(feature = "launch")]
+        Commands::Stop { pidfile, timeout } => {
+            daemon::stop(&pidfile, std::time::Duration::from_secs(timeout))?;
+        }
+        #[cfg(feature = "launch")]
+        Commands::Passwd {
+            config_path,
+            remove,
+        } => match remove {
+            true => {
+                auth::set_password(&config_path, None)?;
+                log::info!("[Auth] Password removed, the UI is no longer protected");
+            }
+            false => {
+                auth::set_password(&config_path, Some(&auth::prompt_password()?))?;
+                log::info!("[Auth] Password updated");
+            }
+        },
     }
     Ok(())
 }
@@ -98,6 +491,23 @@ fn init_log(debug: bool) {
 
 const PORT_RANGE: std::ops::RangeInclusive<usize> = 1024..=65535;
 
+// umask parser
+pub(crate) fn parser_umask(s: &str) -> anyhow::Result<u32> {
+    u32::from_str_radix(s.trim(), 8)
+        .ok()
+        .filter(|umask| *umask <= 0o777)
+        .ok_or_else(|| anyhow::anyhow!(format!("`{}` isn't a octal umask such as 022", s)))
+}
+
+// concurrency parser, a limit of zero would reject every request
+pub(crate) fn parser_concurrency(s: &str) -> anyhow::Result<usize> {
+    s.trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|concurrency| *concurrency >= 1)
+        .ok_or_else(|| anyhow::anyhow!(format!("`{}` isn't a number of at least 1", s)))
+}
+
 // port range parser
 pub(crate) fn parser_port_in_range(s: &str) -> anyhow::Result<u16> {
     let port: usize = s
@@ -120,3 +530,131 @@ pub(crate) fn parser_host(s: &str) -> anyhow::Result<std::net::IpAddr> {
         .map_err(|_| anyhow::anyhow!(format!("`{}` isn't a ip address", s)))?;
     Ok(addr)
 }
+
+// listener parser
+pub(crate) fn parser_listen(s: &str) -> anyhow::Result<listener::ListenSpec> {
+    listener::ListenSpec::parse(s)
+}
+
+// base path parser
+pub(crate) fn parser_base_path(s: &str) -> anyhow::Result<basepath::BasePath> {
+    basepath::BasePath::parse(s)
+}
+
+// access log format parser
+pub(crate) fn parser_access_log_format(s: &str) -> anyhow::Result<accesslog::AccessLogFormat> {
+    accesslog::AccessLogFormat::parse(s)
+}
+
+// memory size parser, bytes or a K, M or G multiple
+pub(crate) fn parser_memory(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" => 10,
+        "M" | "MB" => 20,
+        "G" | "GB" => 30,
+        _ => anyhow::bail!(format!("`{}` isn't a memory size such as 512M or 2G", s)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| anyhow::anyhow!(format!("`{}` isn't a memory size such as 512M or 2G", s)))
+}
+
+// CPU count parser
+pub(crate) fn parser_cpu(s: &str) -> anyhow::Result<f64> {
+    s.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|cpu| cpu.is_finite() && *cpu >= 0.01)
+        .ok_or_else(|| anyhow::anyhow!(format!("`{}` isn't a number of CPUs such as 1.5", s)))
+}
+
+// libc mode parser
+pub(crate) fn parser_libc(s: &str) -> anyhow::Result<libc_asset::LibcMode> {
+    libc_asset::LibcMode::parse(s)
+}
+
+// address block parser
+pub(crate) fn parser_cidr(s: &str) -> anyhow::Result<acl::Cidr> {
+    acl::Cidr::parse(s)
+}
+
+// environment variable parser
+pub(crate) fn parser_env(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!(format!("`{}` isn't a KEY=VALUE pair", s)))?;
+    if key.trim().is_empty() {
+        anyhow::bail!(format!("`{}` has an empty variable name", s))
+    }
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+// boolean parser, accepts the spellings used by config files and UCI
+pub(crate) fn parser_bool(s: &str) -> anyhow::Result<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => anyhow::bail!(format!("`{}` isn't a boolean", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory() {
+        let cases = [
+            ("1024", Some(1024)),
+            ("1024B", Some(1024)),
+            ("64K", Some(65_536)),
+            ("512M", Some(536_870_912)),
+            ("512mb", Some(536_870_912)),
+            (" 2G ", Some(2_147_483_648)),
+            ("2GB", Some(2_147_483_648)),
+            ("17179869183G", Some(18_446_744_072_635_809_792)),
+            ("17179869184G", None),
+            ("18446744073709551616", None),
+            ("0", None),
+            ("0M", None),
+            ("-1M", None),
+            ("1.5G", None),
+            ("2 G", None),
+            ("2T", None),
+            ("M", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parser_memory(input).ok(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn cpu() {
+        let cases = [
+            ("1", Some(1.0)),
+            ("1.5", Some(1.5)),
+            (" 0.25 ", Some(0.25)),
+            ("0.01", Some(0.01)),
+            ("0.009", None),
+            ("0", None),
+            ("-1", None),
+            ("inf", None),
+            ("NaN", None),
+            ("1,5", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parser_cpu(input).ok(), expected, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    ops::Not,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// Upper bounds in seconds of the CGI latency histogram buckets
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+// Prometheus text exposition format
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+    pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        let _ = match labels.is_empty() {
+            true => writeln!(self.0, "{} {}", name, value),
+            false => {
+                let labels = labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, value))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(self.0, "{}{{{}}} {}", name, labels, value)
+            }
+        };
+    }
+
+    // A metric with a single unlabelled sample
+    pub fn metric(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.header(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    pub fn into_response(self) -> rouille::Response {
+        rouille::Response::from_data("text/plain; version=0.0.4; charset=utf-8", self.0)
+    }
+}
+
+pub struct Histogram {
+    // Observations per bucket, the last one is +Inf
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, out: &mut Exposition, name: &str, help: &str) {
+        out.header(name, "histogram", help);
+        let bucket = format!("{}_bucket", name);
+        let mut count = 0;
+        for (index, observations) in self.buckets.iter().enumerate() {
+            count += observations.load(Ordering::Relaxed);
+            let le = match LATENCY_BUCKETS.get(index) {
+                Some(bound) => bound.to_string(),
+                None => String::from("+Inf"),
+            };
+            out.sample(&bucket, &[("le", &le)], count);
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        out.sample(&format!("{}_sum", name), &[], sum);
+        out.sample(&format!("{}_count", name), &[], count);
+    }
+}
+
+// Resource usage summed over the processes of a process group. The counters include the
+// processes that exited since the group was first looked at.
+#[derive(Default)]
+pub struct ProcessStats {
+    pub processes: u64,
+    pub rss_bytes: u64,
+    pub cpu_seconds: f64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    cpu_seconds: f64,
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.cpu_seconds += other.cpu_seconds;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+}
+
+// Counters of the processes seen by the previous look at a group, so that the totals don't go
+// back when a process exits. What a process did after it was last seen is lost.
+#[derive(Default)]
+pub struct ProcessHistory {
+    // Keyed by pid and start time, pids get reused
+    live: HashMap<(u32, u64), Counters>,
+    exited: Counters,
+}
+
+impl ProcessStats {
+    pub fn of_group(pgid: libc::pid_t, history: &mut ProcessHistory) -> Self {
+        let mut stats = ProcessStats::default();
+        let mut totals = history.exited;
+        let mut live = HashMap::new();
+        for (key, rss_bytes, counters) in ProcessStats::processes(pgid) {
+            stats.processes += 1;
+            stats.rss_bytes += rss_bytes;
+            totals.add(&counters);
+            live.insert(key, counters);
+        }
+        for (key, counters) in history.live.drain() {
+            if live.contains_key(&key).not() {
+                history.exited.add(&counters);
+                totals.add(&counters);
+            }
+        }
+        history.live = live;
+        stats.cpu_seconds = totals.cpu_seconds;
+        stats.read_bytes = totals.read_bytes;
+        stats.write_bytes = totals.write_bytes;
+        stats
+    }
+
+    // Key, resident memory and counters of the processes in the group
+    fn processes(pgid: libc::pid_t) -> Vec<((u32, u64), u64, Counters)> {
+        let mut processes = Vec::new();
+        if pgid <= 0 {
+            return processes;
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+        let clock_ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+        let entries = match std::fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return processes,
+        };
+        for entry in entries.flatten() {
+            let pid = match entry
+                .file_name()
+                .to_str()
+                .and_then(|s| s.parse::<u32>().ok())
+            {
+                Some(pid) => pid,
+                None => continue,
+            };
+            let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(stat) => stat,
+                Err(_) => continue,
+            };
+            // The command name may contain spaces, the fields start after its closing paren
+            let fields = match stat.rsplit_once(')') {
+                Some((_, fields)) => fields.split_whitespace().collect::<Vec<_>>(),
+                None => continue,
+            };
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            // fields[0] is field 3 (state) of proc(5)
+            if field(2) != pgid as u64 {
+                continue;
+            }
+            let mut counters = Counters {
+                cpu_seconds: (field(11) + field(12)) as f64 / clock_ticks,
+                ..Counters::default()
+            };
+            if let Ok(io) = std::fs::read_to_string(format!("/proc/{}/io", pid)) {
+                for line in io.lines() {
+                    match line.split_once(':') {
+                        Some(("read_bytes", value)) => {
+                            counters.read_bytes = value.trim().parse::<u64>().unwrap_or(0)
+                        }
+                        Some(("write_bytes", value)) => {
+                            counters.write_bytes = value.trim().parse::<u64>().unwrap_or(0)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            processes.push(((pid, field(19)), field(21) * page_size, counters));
+        }
+        processes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_survive_exited_processes() {
+        let mut history = ProcessHistory::default();
+        let exited = Counters {
+            cpu_seconds: 1.5,
+            read_bytes: 4096,
+            write_bytes: 8192,
+        };
+        history.live.insert((u32::MAX, 0), exited);
+
+        // The process is gone, its last counters stay in the totals of every later look
+        for _ in 0..2 {
+            let stats = ProcessStats::of_group(0, &mut history);
+            assert_eq!(stats.processes, 0);
+            assert_eq!(stats.cpu_seconds, 1.5);
+            assert_eq!(stats.read_bytes, 4096);
+            assert_eq!(stats.write_bytes, 8192);
+            assert!(history.live.is_empty());
+        }
+
+        // Live processes add to them
+        let pgid = unsafe { libc::getpgrp() };
+        let stats = ProcessStats::of_group(pgid, &mut history);
+        assert!(stats.processes >= 1);
+        assert!(stats.cpu_seconds >= 1.5);
+        assert!(stats.read_bytes >= 4096);
+        assert!(stats.write_bytes >= 8192);
+        assert_eq!(history.live.len() as u64, stats.processes);
+    }
+}
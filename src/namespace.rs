@@ -0,0 +1,179 @@
+use std::{
+    ffi::{CString, OsStr},
+    fs::File,
+    ops::Not,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt, io::AsRawFd, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use anyhow::Context;
+
+use crate::{libc_asset::Loader, standard};
+
+// Private mount namespace of the backend and CGI processes. The fake Synology files are bind
+// mounted only inside it, so the host /etc and /usr stay untouched.
+pub struct Namespace {
+    // Keeps the namespace alive after the thread that created it has exited
+    fd: File,
+}
+
+impl Namespace {
+    pub fn create(loader: Option<Loader>) -> anyhow::Result<Arc<Self>> {
+        // unshare(CLONE_NEWNS) only moves the calling thread, the rest of the launcher stays put
+        let fd = std::thread::Builder::new()
+            .name("namespace".to_string())
+            .spawn(move || -> anyhow::Result<File> {
+                if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+                    anyhow::bail!(
+                        "Failed to create a mount namespace, isolation requires root: {}",
+                        std::io::Error::last_os_error()
+                    );
+                }
+                setup(loader.as_ref()).context("Failed to set up the backend mount namespace")?;
+                File::open("/proc/thread-self/ns/mnt").context("Failed to open mount namespace")
+            })?
+            .join()
+            .map_err(|_| anyhow::anyhow!("Mount namespace thread panicked"))??;
+        log::info!("[XunleiLauncher] Backend runs in a private mount namespace");
+        Ok(Arc::new(Self { fd }))
+    }
+
+    // Move the spawned process into the namespace. Must be applied before the process drops
+    // privileges, entering the namespace resets the working directory to `cwd`.
+    pub fn enter(self: &Arc<Self>, cmd: &mut Command, cwd: &str) -> anyhow::Result<()> {
+        let namespace = self.clone();
+        let cwd = CString::new(cwd)?;
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setns(namespace.fd.as_raw_fd(), libc::CLONE_NEWNS) != 0
+                    || libc::chdir(cwd.as_ptr()) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+fn setup(loader: Option<&Loader>) -> anyhow::Result<()> {
+    // Keep receiving host mounts such as new disks, but never leak ours back to the host
+    mount(None, Path::new("/"), libc::MS_REC | libc::MS_SLAVE, None)?;
+
+    let host = Path::new(standard::SYNOPKG_HOST);
+    for target in [standard::SYNO_INFO_PATH, standard::SYNO_AUTHENTICATE_PATH] {
+        let source = host.join(target.trim_start_matches('/'));
+        if source.is_file().not() {
+            anyhow::bail!(format!(
+                "{} is missing, run `xunlei install` first",
+                source.display()
+            ));
+        }
+        bind_file(&source, Path::new(target))?;
+    }
+
+    // Programs spawned by the engine find the bundled glibc loader where they expect it
+    if let Some(loader) = loader {
+        bind_file(&loader.path, &loader.interpreter)?;
+    }
+
+    // Only our own package is visible under /var/packages
+    let base = Path::new(standard::SYNOPKG_PKGBASE);
+    if let Some(packages) = base.parent() {
+        shadow(packages, |name| Some(name) == base.file_name())?;
+    }
+    Ok(())
+}
+
+// Bind `source` onto `target`, creating the target on a tmpfs when it does not exist on the host
+fn bind_file(source: &Path, target: &Path) -> anyhow::Result<()> {
+    if std::fs::symlink_metadata(target).is_err() {
+        let existing = target
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.is_dir())
+            .filter(|dir| *dir != Path::new("/"))
+            .context(format!("No directory to hold {}", target.display()))?;
+        shadow(existing, |_| true)?;
+        if let Some(parent) = target.parent() {
+            standard::create_dir_all(parent, 0o755)?;
+        }
+        File::create(target).context(format!("Failed to create {}", target.display()))?;
+    }
+    mount(Some(source), target, libc::MS_BIND, None)
+}
+
+// Mount a tmpfs over `dir` and bind back the original entries that `keep` accepts, including
+// the mounts below them. New files can then be created in `dir` without touching the host.
+fn shadow(dir: &Path, keep: impl Fn(&OsStr) -> bool) -> anyhow::Result<()> {
+    let metadata = std::fs::metadata(dir)?;
+    // The original directory stays reachable through the descriptor once it is covered
+    let original = File::open(dir).context(format!("Failed to open {}", dir.display()))?;
+    let original_path = PathBuf::from(format!("/proc/self/fd/{}", original.as_raw_fd()));
+    let mode = format!("mode={:o}", metadata.mode() & 0o7777);
+    mount(Some(Path::new("tmpfs")), dir, 0, Some(("tmpfs", &mode)))?;
+
+    for entry in std::fs::read_dir(&original_path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if keep(&name).not() {
+            continue;
+        }
+        let (source, target) = (original_path.join(&name), dir.join(&name));
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&source)?, &target)?;
+            continue;
+        }
+        // Files, sockets and device nodes alike are bound onto an empty file
+        match file_type.is_dir() {
+            true => std::fs::create_dir(&target)?,
+            false => File::create(&target).map(|_| ())?,
+        }
+        mount(Some(&source), &target, libc::MS_BIND | libc::MS_REC, None)?;
+    }
+    Ok(())
+}
+
+// mount(2), `filesystem` is the type and options of a new mount, unset for binds and remounts
+fn mount(
+    source: Option<&Path>,
+    target: &Path,
+    flags: libc::c_ulong,
+    filesystem: Option<(&str, &str)>,
+) -> anyhow::Result<()> {
+    let source_c = source
+        .map(|source| CString::new(source.as_os_str().as_bytes()))
+        .transpose()?;
+    let target_c = CString::new(target.as_os_str().as_bytes())?;
+    let (fstype, options) = match filesystem {
+        Some((fstype, options)) => (Some(CString::new(fstype)?), Some(CString::new(options)?)),
+        None => (None, None),
+    };
+    let res = unsafe {
+        libc::mount(
+            source_c.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            target_c.as_ptr(),
+            fstype.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            flags,
+            options
+                .as_ref()
+                .map_or(std::ptr::null(), |s| s.as_ptr() as *const libc::c_void),
+        )
+    };
+    if res != 0 {
+        anyhow::bail!(
+            "Failed to mount {} on {}: {}",
+            source
+                .map(Path::display)
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            target.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
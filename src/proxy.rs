@@ -0,0 +1,303 @@
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Not,
+};
+
+use crate::acl::{self, Cidr};
+
+// Signature that opens a PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// Longest PROXY protocol v1 header, CRLF included
+const V1_MAX_LENGTH: usize = 107;
+
+// Read the PROXY protocol v1 or v2 header that opens a connection and return the source address
+// it announces, None for LOCAL and UNKNOWN connections such as health checks of the proxy
+pub fn read_header(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    // Shorter than any v1 header, so nothing past the header is consumed
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head)?;
+    if head == V2_SIGNATURE {
+        read_v2(stream)
+    } else if head.starts_with(b"PROXY ") {
+        read_v1(stream, &head)
+    } else {
+        Err(invalid(String::from("missing PROXY protocol header")))
+    }
+}
+
+// `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`
+fn read_v1(stream: &mut impl Read, head: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = head.to_vec();
+    while line.ends_with(b"\r\n").not() {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid(String::from(
+                "PROXY protocol v1 header is too long",
+            )));
+        }
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = String::from_utf8_lossy(&line[..line.len() - 2]).into_owned();
+    let fields = line.split(' ').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            match (source.parse::<IpAddr>(), port.parse::<u16>()) {
+                // The address family has to match the protocol
+                (Ok(ip), Ok(port)) if ip.is_ipv4() == (*protocol == "TCP4") => {
+                    Ok(Some(SocketAddr::new(ip, port)))
+                }
+                _ => Err(invalid(format!(
+                    "invalid PROXY protocol v1 header `{}`",
+                    line
+                ))),
+            }
+        }
+        _ => Err(invalid(format!(
+            "invalid PROXY protocol v1 header `{}`",
+            line
+        ))),
+    }
+}
+
+fn read_v2(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head)?;
+    let (version_command, family) = (head[0], head[1]);
+    // The address block is followed by optional TLVs, all of it belongs to the header
+    let mut body = vec![0u8; u16::from_be_bytes([head[2], head[3]]) as usize];
+    stream.read_exact(&mut body)?;
+
+    match (version_command >> 4, version_command & 0x0f) {
+        (2, 0) => return Ok(None),
+        (2, 1) => {}
+        _ => {
+            return Err(invalid(format!(
+                "unsupported PROXY protocol v2 version and command {:#04x}",
+                version_command
+            )))
+        }
+    }
+    match family >> 4 {
+        // AF_INET: source and destination addresses, then ports
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V4(ip),
+                u16::from_be_bytes([body[8], body[9]]),
+            )))
+        }
+        // AF_INET6
+        2 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                u16::from_be_bytes([body[32], body[33]]),
+            )))
+        }
+        // AF_UNSPEC and AF_UNIX carry no client address
+        0 | 3 => Ok(None),
+        _ => Err(invalid(String::from(
+            "truncated PROXY protocol v2 address block",
+        ))),
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Client address of a request from a trusted proxy, taken from the `Forwarded` header or else
+// `X-Forwarded-For`. The chain is walked from the nearest hop and the first address that isn't
+// a trusted proxy is the client, addresses forwarded without a port get port 0.
+pub fn client_addr(request: &rouille::Request, peer: SocketAddr, trusted: &[Cidr]) -> SocketAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    let mut client = peer;
+    let hops = match is_trusted(peer.ip()) {
+        true => forwarded_for(request),
+        false => Vec::new(),
+    };
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(hop) => client = hop,
+            // Obfuscated or unknown hops end the chain of trust
+            None => break,
+        }
+        if is_trusted(client.ip()).not() {
+            break;
+        }
+    }
+    SocketAddr::new(acl::canonical(client.ip()), client.port())
+}
+
+// Forwarded addresses in order, from the original client to the nearest proxy
+fn forwarded_for(request: &rouille::Request) -> Vec<Option<SocketAddr>> {
+    let values = |name: &str| {
+        request
+            .headers()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|value| value.is_empty().not())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    // Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"
+    let forwarded = values("Forwarded");
+    if forwarded.is_empty().not() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value))
+            })
+            .collect();
+    }
+    values("X-Forwarded-For")
+        .iter()
+        .map(|value| parse_node(value))
+        .collect()
+}
+
+// `192.0.2.1`, `192.0.2.1:80`, `2001:db8::1` or `[2001:db8::1]:80`, optionally quoted
+fn parse_node(value: &str) -> Option<SocketAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = value.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Data the client sends after the header, it must be left in the stream
+    const PAYLOAD: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+    fn v2(version_command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[version_command, family]);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    // Source 192.0.2.1:56324, destination 192.0.2.2:443
+    fn inet() -> Vec<u8> {
+        [[192, 0, 2, 1], [192, 0, 2, 2]]
+            .concat()
+            .into_iter()
+            .chain(56324u16.to_be_bytes())
+            .chain(443u16.to_be_bytes())
+            .collect()
+    }
+
+    // Source [2001:db8::1]:56324, destination [2001:db8::2]:443
+    fn inet6() -> Vec<u8> {
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        [source.octets(), destination.octets()]
+            .concat()
+            .into_iter()
+            .chain(56324u16.to_be_bytes())
+            .chain(443u16.to_be_bytes())
+            .collect()
+    }
+
+    // Ok(Some(addr)) as a string, "none" for connections without address and "error" on failure
+    fn read(header: &[u8]) -> String {
+        let mut stream = io::Cursor::new([header, PAYLOAD].concat());
+        let result = match read_header(&mut stream) {
+            Ok(Some(addr)) => addr.to_string(),
+            Ok(None) => String::from("none"),
+            Err(_) => return String::from("error"),
+        };
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, PAYLOAD, "{:?}", String::from_utf8_lossy(header));
+        result
+    }
+
+    #[test]
+    fn read_v1() {
+        let cases: [(&[u8], &str); 12] = [
+            (
+                b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+                "192.0.2.1:56324",
+            ),
+            (
+                b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n",
+                "[2001:db8::1]:56324",
+            ),
+            (b"PROXY UNKNOWN\r\n", "none"),
+            (
+                b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n",
+                "none",
+            ),
+            (b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n", "error"),
+            (b"PROXY TCP6 192.0.2.1 192.0.2.2 56324 443\r\n", "error"),
+            (b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n", "error"),
+            (b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n", "error"),
+            (b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n", "error"),
+            (b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\n", "error"),
+            (b"GET / HTTP/1.1\r\n\r\n", "error"),
+            (&[b'P'; 200], "error"),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(
+                read(header),
+                expected,
+                "{:?}",
+                String::from_utf8_lossy(header)
+            );
+        }
+
+        let long = [b"PROXY ".as_slice(), &[b'A'; 120], b"\r\n"].concat();
+        assert_eq!(read(&long), "error");
+        // Truncated before the CRLF
+        let mut stream = io::Cursor::new(b"PROXY TCP4 192.0.2.1 192.0".to_vec());
+        assert!(read_header(&mut stream).is_err());
+    }
+
+    #[test]
+    fn read_v2() {
+        let cases = [
+            (v2(0x21, 0x11, &inet()), "192.0.2.1:56324"),
+            (v2(0x21, 0x21, &inet6()), "[2001:db8::1]:56324"),
+            // TLVs after the address block are skipped
+            (
+                v2(0x21, 0x11, &[inet(), vec![0x04, 0x00, 0x01, 0xaa]].concat()),
+                "192.0.2.1:56324",
+            ),
+            (v2(0x20, 0x11, &inet()), "none"),
+            (v2(0x20, 0x00, &[]), "none"),
+            (v2(0x21, 0x00, &[]), "none"),
+            (v2(0x21, 0x31, &[0u8; 216]), "none"),
+            (v2(0x21, 0x11, &inet()[..8]), "error"),
+            (v2(0x21, 0x21, &inet6()[..32]), "error"),
+            (v2(0x21, 0x41, &inet()), "error"),
+            (v2(0x11, 0x11, &inet()), "error"),
+            (v2(0x22, 0x11, &inet()), "error"),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(read(&header), expected, "{:02x?}", header);
+        }
+
+        // The length announces more bytes than the stream holds
+        let mut truncated = v2(0x21, 0x11, &inet());
+        truncated.truncate(truncated.len() - 4);
+        assert!(read_header(&mut io::Cursor::new(truncated)).is_err());
+        let mut stream = io::Cursor::new(V2_SIGNATURE[..8].to_vec());
+        assert!(read_header(&mut stream).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    ops::Not,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::MetadataExt,
+        io::{AsRawFd, FromRawFd},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use anyhow::Context;
+
+// Buffer for the string fields of getpwnam_r/getgrnam_r
+const NSS_BUFFER_SIZE: usize = 16 * 1024;
+
+// Account and umask of the backend and CGI processes. The launcher keeps running as root so
+// that it can bind the UI and prepare the directories, only its children drop privileges.
+#[derive(Clone, Default, PartialEq)]
+pub struct RunAs {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub umask: Option<u32>,
+    // Supplementary groups of the user, looked up up front as NSS isn't safe to use after fork
+    groups: Arc<[libc::gid_t]>,
+}
+
+impl RunAs {
+    // `user` and `group` are names or numeric ids, the group defaults to the user's primary group
+    pub fn resolve(
+        user: Option<&str>,
+        group: Option<&str>,
+        umask: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        let (uid, primary_gid, name) = match user {
+            Some(user) => {
+                let (uid, gid, name) = lookup_user(user)?;
+                (Some(uid), gid, name)
+            }
+            None => (None, None, None),
+        };
+        let gid = match group {
+            Some(group) => Some(lookup_group(group)?),
+            // A bare PUID without passwd entry, as in containers, gets the group of the same id
+            None => primary_gid.or(uid),
+        };
+
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid != 0 && (uid.unwrap_or(euid) != euid || gid.unwrap_or(egid) != egid) {
+            anyhow::bail!(
+                "Running the backend as another user requires the launcher to run as root"
+            )
+        }
+        let groups = match (name, gid) {
+            (Some(name), Some(gid)) if euid == 0 => group_list(&name, gid)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            uid,
+            gid,
+            umask,
+            groups: groups.into(),
+        })
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.uid.is_none() && self.gid.is_none() && self.umask.is_none()
+    }
+
+    pub fn switches_account(&self) -> bool {
+        self.uid.is_some() || self.gid.is_some()
+    }
+
+    // Switch the spawned process to the account right before exec. Done in pre_exec rather than
+    // with Command::uid so that it runs after the process entered the backend mount namespace.
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.is_default() {
+            return;
+        }
+        let run_as = self.clone();
+        unsafe {
+            cmd.pre_exec(move || run_as.switch());
+        }
+    }
+
+    // Runs in the forked child, only async-signal-safe calls
+    fn switch(&self) -> std::io::Result<()> {
+        let check = |res: libc::c_int| match res {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        };
+        if let Some(umask) = self.umask {
+            unsafe { libc::umask(umask as libc::mode_t) };
+        }
+        if self.switches_account() {
+            // Replace the supplementary groups of root with the user's, as initgroups does
+            if unsafe { libc::geteuid() } == 0 {
+                check(unsafe { libc::setgroups(self.groups.len(), self.groups.as_ptr()) })?;
+            }
+        }
+        if let Some(gid) = self.gid {
+            check(unsafe { libc::setgid(gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { libc::setuid(uid) })?;
+        }
+        Ok(())
+    }
+
+    // Hand `path` over to the account, `recursive` includes everything below it but `skip`. The
+    // account can write to these directories, so every entry is opened without following symlinks
+    // and changed through its descriptor. Hard links and files of other users are left alone, they
+    // may point outside of the tree.
+    pub fn chown(&self, path: &Path, recursive: bool, skip: &[PathBuf]) -> anyhow::Result<()> {
+        if self.switches_account().not() {
+            return Ok(());
+        }
+        let parent = path.parent().context(format!(
+            "Refusing to change the owner of {}",
+            path.display()
+        ))?;
+        let parent = File::open(parent).context(format!("Failed to open {}", parent.display()))?;
+        self.chown_at(&parent, path, recursive, skip)
+    }
+
+    // `path` is the entry of the open directory `dir` with the same name
+    fn chown_at(
+        &self,
+        dir: &File,
+        path: &Path,
+        recursive: bool,
+        skip: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        if skip.iter().any(|skip| skip == path) {
+            return Ok(());
+        }
+        let name = CString::new(path.file_name().unwrap_or_default().as_bytes())?;
+        let fd = unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return Ok(());
+            }
+            return Err(e).context(format!("Failed to open {}", path.display()));
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let metadata = file
+            .metadata()
+            .context(format!("Failed to stat {}", path.display()))?;
+        if metadata.is_dir().not() && metadata.nlink() > 1 {
+            log::warn!(
+                "[XunleiLauncher] Not changing the owner of hard link {}",
+                path.display()
+            );
+            return Ok(());
+        }
+        if metadata.uid() != 0 && Some(metadata.uid()) != self.uid {
+            return Ok(());
+        }
+
+        let (uid, gid) = (
+            self.uid.unwrap_or(metadata.uid()),
+            self.gid.unwrap_or(metadata.gid()),
+        );
+        if metadata.uid() != uid || metadata.gid() != gid {
+            let empty = CString::default();
+            if unsafe { libc::fchownat(fd, empty.as_ptr(), uid, gid, libc::AT_EMPTY_PATH) } != 0 {
+                return Err(std::io::Error::last_os_error()).context(format!(
+                    "Failed to set owner of {} to {}:{}",
+                    path.display(),
+                    uid,
+                    gid
+                ));
+            }
+        }
+        if recursive && metadata.is_dir() {
+            let fd = unsafe {
+                libc::openat(
+                    fd,
+                    c".".as_ptr(),
+                    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context(format!("Failed to open {}", path.display()));
+            }
+            let dir = unsafe { File::from_raw_fd(fd) };
+            for entry in std::fs::read_dir(format!("/proc/self/fd/{}", dir.as_raw_fd()))? {
+                self.chown_at(&dir, &path.join(entry?.file_name()), true, skip)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RunAs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = |id: Option<u32>| id.map(|id| id.to_string()).unwrap_or_else(|| "-".into());
+        write!(f, "uid {} gid {}", id(self.uid), id(self.gid))?;
+        if let Some(umask) = self.umask {
+            write!(f, " umask {:03o}", umask)?;
+        }
+        Ok(())
+    }
+}
+
+// uid of a user name or id, with the primary group and name when the user has a passwd entry
+fn lookup_user(user: &str) -> anyhow::Result<(u32, Option<u32>, Option<CString>)> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let id = user.parse::<u32>().ok();
+    let code = match id {
+        Some(uid) => unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        },
+        None => {
+            let name = CString::new(user)?;
+            unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            }
+        }
+    };
+    match (result.is_null().not(), id) {
+        (true, _) => {
+            let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+            Ok((passwd.pw_uid, Some(passwd.pw_gid), Some(name.to_owned())))
+        }
+        (false, Some(uid)) => Ok((uid, None, None)),
+        (false, None) if code != 0 => anyhow::bail!(format!(
+            "Failed to look up user `{}`: {}",
+            user,
+            std::io::Error::from_raw_os_error(code)
+        )),
+        (false, None) => anyhow::bail!(format!("No such user `{}`", user)),
+    }
+}
+
+// Groups `name` is a member of in the group database, plus `gid`
+fn group_list(name: &CString, gid: u32) -> anyhow::Result<Vec<libc::gid_t>> {
+    let mut groups = vec![0 as libc::gid_t; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let res =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        // On overflow the required size is stored in `count`
+        if res >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        if count as usize <= groups.len() {
+            anyhow::bail!(format!(
+                "Failed to look up the groups of {}",
+                name.to_string_lossy()
+            ));
+        }
+        groups.resize(count as usize, 0);
+    }
+}
+
+fn lookup_group(group: &str) -> anyhow::Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+    let name = CString::new(group)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    let code = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    match result.is_null().not() {
+        true => Ok(entry.gr_gid),
+        false if code != 0 => anyhow::bail!(format!(
+            "Failed to look up group `{}`: {}",
+            group,
+            std::io::Error::from_raw_os_error(code)
+        )),
+        false => anyhow::bail!(format!("No such group `{}`", group)),
+    }
+}
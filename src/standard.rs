@@ -23,9 +23,7 @@ pub const SYNOPKG_PKGBASE: &str = "/var/packages/pan-xunlei-com";
 pub const SYNOPKG_PKGDEST: &str = "/var/packages/pan-xunlei-com/target";
 pub const SYNOPKG_VAR: &str = "/var/packages/pan-xunlei-com/target/var/";
 pub const SYNOPKG_HOST: &str = "/var/packages/pan-xunlei-com/target/host";
-#[cfg(all(target_os = "linux", target_env = "musl"))]
 pub const SYNOPKG_LIB: &str = "/var/packages/pan-xunlei-com/target/host/lib";
-#[cfg(all(target_os = "linux", target_env = "musl"))]
 pub const SYS_LIB: &str = "/lib";
 pub const SYNOPKG_CLI_WEB: &str = "/var/packages/pan-xunlei-com/target/xunlei-pan-cli-web";
 #[cfg(target_arch = "x86_64")]
@@ -42,9 +40,16 @@ pub const LAUNCH_PID_FILE: &str =
     "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com-launcher.pid";
 pub const LAUNCH_LOG_FILE: &str =
     "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com-launcher.log";
+pub const DAEMON_LOG_FILE: &str =
+    "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com-daemon.log";
 pub const INST_LOG: &str = "/var/packages/pan-xunlei-com/target/var/pan-xunlei-com_install.log";
 pub const SYNOPKG_WEB_UI_HOME: &str = "/webman/3rdparty/pan-xunlei-com/index.cgi/";
 pub const TMP_DOWNLOAD_PATH: &str = "/tmp/downloads";
+pub const TLS_CERT_FILE: &str = "xunlei-ui.crt";
+pub const TLS_KEY_FILE: &str = "xunlei-ui.key";
+pub const PASSWORD_FILE: &str = "xunlei-ui.passwd";
+// Config directory of a backend running as another user, below the launcher's one
+pub const BACKEND_DATA_DIR: &str = "data";
 
 pub fn set_permissions(target_path: &str, uid: u32, gid: u32) -> anyhow::Result<()> {
     let filename = std::ffi::OsStr::new(target_path).as_bytes();
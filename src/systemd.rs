@@ -17,27 +17,35 @@ use crate::Running;
 
 pub struct XunleiInstall {
     description: &'static str,
-    host: std::net::IpAddr,
     port: u16,
     download_path: PathBuf,
     config_path: PathBuf,
+    launch_args: Vec<String>,
     uid: u32,
     gid: u32,
+    // The launcher mounts the Synology files in a private namespace, the host is left alone
+    isolate: bool,
 }
 
-impl From<Config> for XunleiInstall {
-    fn from(config: Config) -> Self {
+impl TryFrom<&Config> for XunleiInstall {
+    type Error = anyhow::Error;
+
+    // The service reads the configuration file itself, only the command line goes into the unit
+    // so that the file's values are neither applied twice nor kept after they were removed
+    fn try_from(cli: &Config) -> anyhow::Result<Self> {
+        let config = cli.load()?;
         let uid = unsafe { libc::getuid() };
         let gid = unsafe { libc::getgid() };
-        Self {
+        Ok(Self {
             description: "Thunder remote download service",
-            host: config.host,
+            launch_args: cli.launch_args(),
             port: config.port,
             download_path: config.download_path,
             config_path: config.config_path,
             uid,
             gid,
-        }
+            isolate: config.isolate,
+        })
     }
 }
 
@@ -119,7 +127,7 @@ impl XunleiInstall {
             ))?,
             0o755,
         )?;
-        let mut byte_arr = vec![0u8; 32];
+        let mut byte_arr = [0u8; 32];
         rand::thread_rng().fill(&mut byte_arr[..]);
         let hex_string = byte_arr
             .iter()
@@ -155,6 +163,15 @@ impl XunleiInstall {
             0o755,
         )?;
 
+        if self.isolate {
+            // Links left by an earlier installation without isolation
+            for link in remove_host_links()? {
+                log::info!("[XunleiInstall] Removed symlink: {}", link);
+            }
+            log::info!("[XunleiInstall] Installation completed");
+            return Ok(std::env::current_exe()?);
+        }
+
         // symlink
         unsafe {
             if Path::new(standard::SYNO_INFO_PATH).exists().not() {
@@ -204,26 +221,27 @@ impl XunleiInstall {
                 
                 [Service]
                 Type=simple
-                ExecStart={} launch -h {} -p {} -d {} -c {}
+                ExecStart={} launch {}
+                ExecReload=/bin/kill -HUP $MAINPID
+                Restart=on-failure
+                RestartSec=5
                 LimitNOFILE=1024
                 LimitNPROC=512
+                Delegate=yes
                 User={}
                 
                 [Install]
                 WantedBy=multi-user.target"#,
             self.description,
             launch.display(),
-            self.host,
-            self.port,
-            self.download_path.display(),
-            self.config_path.display(),
+            self.launch_args.join(" "),
             self.uid
         );
 
         standard::write_file(
             &PathBuf::from(standard::SYSTEMCTL_UNIT_FILE),
             std::borrow::Cow::Borrowed(systemctl_unit.as_bytes()),
-            0o666,
+            0o644,
         )?;
 
         Systemd::systemctl(["daemon-reload"])?;
@@ -251,6 +269,11 @@ impl XunleiUninstall {
                 log::info!("[XunleiUninstall] Uninstall xunlei service");
             }
         }
+        // The loader and Synology links would dangle once the package is gone
+        crate::libc_asset::restore_loader()?;
+        for link in remove_host_links()? {
+            log::info!("[XunleiUninstall] Removed symlink: {}", link);
+        }
         let path = PathBuf::from(standard::SYNOPKG_PKGBASE);
         if path.exists() {
             std::fs::remove_dir_all(path)?;
@@ -273,6 +296,23 @@ impl Running for XunleiUninstall {
     }
 }
 
+// Remove the /etc/synoinfo.conf and authenticate.cgi symlinks into the package, files that
+// don't belong to us are left alone. Returns the removed links.
+fn remove_host_links() -> anyhow::Result<Vec<&'static str>> {
+    let mut removed = Vec::new();
+    for link in [standard::SYNO_INFO_PATH, standard::SYNO_AUTHENTICATE_PATH] {
+        let target = match std::fs::read_link(link) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if target.starts_with(standard::SYNOPKG_HOST) {
+            std::fs::remove_file(link).context(format!("Failed to remove {}", link))?;
+            removed.push(link);
+        }
+    }
+    Ok(removed)
+}
+
 struct Systemd;
 
 impl Systemd {
@@ -7,7 +7,7 @@ use std::{io::Write, ops::Not, path::PathBuf};
 pub trait Xunlei {
     fn version(&self) -> anyhow::Result<String>;
 
-    fn get(&self, filename: &str) -> anyhow::Result<Cow<[u8]>>;
+    fn get(&self, filename: &str) -> anyhow::Result<Cow<'_, [u8]>>;
 
     fn iter(&self) -> anyhow::Result<Vec<String>>;
 }
@@ -32,7 +32,7 @@ impl Xunlei for XunleiEmbedAsset {
         Ok(String::from(version))
     }
 
-    fn get(&self, filename: &str) -> anyhow::Result<Cow<[u8]>> {
+    fn get(&self, filename: &str) -> anyhow::Result<Cow<'_, [u8]>> {
         let bin = Asset::get(filename).context("Failed to get bin asset")?;
         Ok(bin.data)
     }
@@ -124,7 +124,7 @@ impl Xunlei for XunleiLocalAsset {
         )?)
     }
 
-    fn get(&self, filename: &str) -> anyhow::Result<Cow<[u8]>> {
+    fn get(&self, filename: &str) -> anyhow::Result<Cow<'_, [u8]>> {
         let vec = std::fs::read(PathBuf::from(&self.tmp_path).join(filename))?;
         Ok(std::borrow::Cow::from(vec))
     }
@@ -132,12 +132,10 @@ impl Xunlei for XunleiLocalAsset {
     fn iter(&self) -> anyhow::Result<Vec<String>> {
         let entries = std::fs::read_dir(&self.tmp_path)?;
         let mut file_names = Vec::new();
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name() {
-                    file_names.push(file_name.to_string_lossy().to_string());
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(file_name) = path.file_name() {
+                file_names.push(file_name.to_string_lossy().to_string());
             }
         }
         Ok(file_names)